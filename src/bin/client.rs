@@ -1,4 +1,5 @@
 use dobby::cli::{format::Format, Repl};
+use dobby::core::types::Coercion;
 use structopt::StructOpt;
 
 /// A database engine as poor as a house elf
@@ -18,11 +19,16 @@ struct Options {
         possible_values = &["ascii", "json", "csv", "html"]
     )]
     format: Format,
+
+    /// How values are converted to the datatypes of their columns,
+    /// instead of the server's default
+    #[structopt(long, possible_values = &["strict", "lenient", "extended"])]
+    coercion: Option<Coercion>,
 }
 
 #[tokio::main]
 async fn main() {
     let opt = Options::from_args();
-    let mut repl = Repl::init(opt.url, opt.format).await;
+    let mut repl = Repl::init(opt.url, opt.format, opt.coercion).await;
     repl.run().await;
 }
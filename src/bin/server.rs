@@ -1,17 +1,24 @@
+#[cfg(feature = "sqlite")]
+use dobby::core::Sqlite;
 use dobby::{
-    core::{Database, Dobby, Sqlite},
+    core::{database::SafeMode, types::Coercion, Database, Dobby},
     grpc, rest,
 };
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use structopt::StructOpt;
 
 /// A database engine as poor as a house elf
 #[derive(Debug, StructOpt)]
 #[structopt(name = "dobby")]
 struct Options {
-    /// Path to the database directory
+    /// Path to the database directory, or to an existing SQLite file with --sqlite
     #[structopt(parse(from_os_str))]
+    #[cfg_attr(
+        not(feature = "sqlite"),
+        structopt(help = "Path to the database directory")
+    )]
     path: PathBuf,
 
     /// Creates a new database called <name>
@@ -27,42 +34,76 @@ struct Options {
     rest: Option<u16>,
 
     /// Use sqlite as the backend
+    #[cfg(feature = "sqlite")]
     #[structopt(long)]
     sqlite: bool,
+
+    /// Allow updating, deleting and restoring every row without an explicit `all` flag
+    #[structopt(long = "unsafe")]
+    unsafe_mode: bool,
+
+    /// How values are converted to the datatypes of their columns, unless a request
+    /// asks otherwise: strict, lenient or extended
+    #[structopt(long, name = "mode", default_value = "lenient")]
+    coercion: Coercion,
+
+    /// Keep dropped tables restorable for <days>
+    #[structopt(long, name = "days", default_value = "7")]
+    retention: u64,
 }
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    // warnings, like the columns of SQLite files left out of the schema, are shown by default
+    let mut logger = pretty_env_logger::formatted_builder();
+    logger.filter_level(log::LevelFilter::Warn);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        logger.parse_filters(&filters);
+    }
+    logger.init();
     let options = Options::from_args();
 
     if options.grpc.is_none() && options.rest.is_none() {
         panic!("No server specified");
     }
 
-    let db = if options.sqlite {
-        let db = if let Some(name) = options.new {
-            Sqlite::create(options.path, name)
-        } else {
-            Sqlite::open(options.path)
-        };
-        Arc::new(Mutex::new(db)) as Arc<dyn Database>
+    let retention = Duration::from_secs(options.retention * 24 * 60 * 60);
+
+    let db = match options.new {
+        #[cfg(feature = "sqlite")]
+        new if options.sqlite => {
+            let db = if let Some(name) = new {
+                Sqlite::create(options.path, name)
+            } else if options.path.is_file() {
+                Sqlite::introspect(options.path)
+            } else {
+                Sqlite::open(options.path)
+            };
+            Arc::new(Mutex::new(db.with_retention(retention))) as Arc<dyn Database>
+        }
+        new => {
+            let db = if let Some(name) = new {
+                Dobby::create(options.path, name)
+            } else {
+                Dobby::open(options.path)
+            };
+            Arc::new(Mutex::new(db.with_retention(retention))) as Arc<dyn Database>
+        }
+    };
+
+    let db = if options.unsafe_mode {
+        db
     } else {
-        let db = if let Some(name) = options.new {
-            Dobby::create(options.path, name)
-        } else {
-            Dobby::open(options.path)
-        };
-        Arc::new(Mutex::new(db)) as Arc<dyn Database>
+        Arc::new(SafeMode(db)) as Arc<dyn Database>
     };
 
     let grpc_server = options
         .grpc
-        .map(|port| grpc::serve(Arc::clone(&db), ([0, 0, 0, 0], port)));
+        .map(|port| grpc::serve(Arc::clone(&db), ([0, 0, 0, 0], port), options.coercion));
 
     let rest_server = options
         .rest
-        .map(|port| rest::serve(Arc::clone(&db), ([0, 0, 0, 0], port)));
+        .map(|port| rest::serve(Arc::clone(&db), ([0, 0, 0, 0], port), options.coercion));
 
     tokio::select! {
         _ = async { grpc_server.unwrap().await }, if grpc_server.is_some() => {},
@@ -1,5 +1,6 @@
-use crate::core::types::{DataType, TypedValue};
+use crate::core::types::{json_path_operand, DataType, Reference, Rule, TypedValue};
 use crate::grpc::proto;
+use std::collections::HashMap;
 use std::error::Error;
 use structopt::{clap::AppSettings, StructOpt};
 
@@ -43,6 +44,9 @@ pub enum Command {
         /// The filter to apply to the rows, in the form of column=value
         #[structopt(short = "w", long = "where", parse(try_from_str = parse_key_val))]
         conditions: Vec<(String, TypedValue)>,
+        /// Confirm updating every row when no filter is given
+        #[structopt(long)]
+        all: bool,
     },
 
     /// Delete rows from the table
@@ -54,6 +58,34 @@ pub enum Command {
         /// The filter to apply to the rows, in the form of column=value
         #[structopt(short = "w", long = "where", parse(try_from_str = parse_key_val))]
         conditions: Vec<(String, TypedValue)>,
+        /// Confirm deleting every row when no filter is given
+        #[structopt(long)]
+        all: bool,
+    },
+
+    /// List deleted rows that can be restored
+    #[structopt(setting = AppSettings::DisableVersion)]
+    Deleted {
+        /// The table to read from
+        #[structopt(short, long)]
+        table: String,
+        /// The filter to apply to the rows, in the form of column=value
+        #[structopt(short = "w", long = "where", parse(try_from_str = parse_key_val))]
+        conditions: Vec<(String, TypedValue)>,
+    },
+
+    /// Restore deleted rows
+    #[structopt(setting = AppSettings::DisableVersion)]
+    Undelete {
+        /// The table to restore rows in
+        #[structopt(short, long)]
+        table: String,
+        /// The filter to apply to the rows, in the form of column=value
+        #[structopt(short = "w", long = "where", parse(try_from_str = parse_key_val))]
+        conditions: Vec<(String, TypedValue)>,
+        /// Confirm restoring every deleted row when no filter is given
+        #[structopt(long)]
+        all: bool,
     },
 
     /// Drop the whole table
@@ -64,6 +96,26 @@ pub enum Command {
         table: String,
     },
 
+    /// Restore a dropped table from the trash
+    #[structopt(setting = AppSettings::DisableVersion)]
+    Undrop {
+        /// The table to restore
+        #[structopt(short, long)]
+        table: String,
+    },
+
+    /// List the dropped tables in the trash
+    #[structopt(setting = AppSettings::DisableVersion)]
+    Trash,
+
+    /// Delete dropped tables for good
+    #[structopt(setting = AppSettings::DisableVersion)]
+    Purge {
+        /// The dropped table to delete, or the whole trash if omitted
+        #[structopt(short, long)]
+        table: Option<String>,
+    },
+
     /// Create a new table
     #[structopt(setting = AppSettings::DisableVersion)]
     Create {
@@ -71,20 +123,79 @@ pub enum Command {
         #[structopt(short, long)]
         table: String,
         /// The columns to create, specified as column=type
-        /// where type is one of: int, float, char, string, char_invl, string_invl
+        /// where type is one of: int, float, char, string, char_invl, string_invl,
+        /// bool, date, timestamp, int_invl, float_invl, timestamp_invl, bytes, json, enum, decimal, uuid
         #[structopt(short, long, parse(try_from_str = parse_key_val))]
         columns: Vec<(String, DataType)>,
+        /// The labels of the enum columns, specified as column=label1,label2,...
+        #[structopt(long = "labels", parse(try_from_str = parse_labels))]
+        labels: Vec<(String, Vec<String>)>,
+        /// The digits of the decimal columns, specified as column=precision,scale
+        #[structopt(long = "precision", parse(try_from_str = parse_precision))]
+        precisions: Vec<(String, proto::Precision)>,
+        /// The uuid columns to fill in automatically on insert, specified as column=version
+        /// where version is one of: v4, v7
+        #[structopt(long = "generate", parse(try_from_str = parse_uuid_version))]
+        generated: Vec<(String, proto::UuidVersion)>,
+        /// The int column to fill in automatically on insert
+        #[structopt(long)]
+        auto_increment: Option<String>,
+        /// Values to insert when the columns are omitted, specified as column=value
+        #[structopt(long = "default", parse(try_from_str = parse_key_val))]
+        defaults: Vec<(String, TypedValue)>,
+        /// Constraints on the values, specified as column=rule:value
+        /// where rule is one of: min, max, max_length, pattern, min_width, max_width
+        #[structopt(long = "rule", parse(try_from_str = parse_key_val))]
+        rules: Vec<(String, Rule)>,
+        /// Key columns of other tables that the columns reference,
+        /// specified as column=table.key or column=table.key:cascade
+        #[structopt(long = "reference", parse(try_from_str = parse_key_val))]
+        references: Vec<(String, Reference)>,
     },
 
-    /// Rename columns in the table
+    /// Rename the table or columns in it
     #[structopt(setting = AppSettings::DisableVersion)]
     Rename {
-        /// The table to rename columns in
+        /// The table to rename or rename columns in
         #[structopt(short, long)]
         table: String,
         /// The columns to rename, specified as old=new
         #[structopt(short, long, parse(try_from_str = parse_key_val))]
         columns: Vec<(String, String)>,
+        /// The new name of the table
+        #[structopt(long, conflicts_with = "columns")]
+        to: Option<String>,
+    },
+
+    /// Add, drop or change datatypes of columns in the table
+    #[structopt(setting = AppSettings::DisableVersion)]
+    Alter {
+        /// The table to alter
+        #[structopt(short, long)]
+        table: String,
+        /// The columns to add, specified as column=type
+        #[structopt(short, long, parse(try_from_str = parse_key_val))]
+        add: Vec<(String, DataType)>,
+        /// The columns to drop
+        #[structopt(short, long)]
+        drop: Vec<String>,
+        /// The new datatypes of columns, specified as column=type
+        #[structopt(short, long, parse(try_from_str = parse_key_val))]
+        retype: Vec<(String, DataType)>,
+        /// Defaults of the added columns, which also fill them in the existing rows,
+        /// specified as column=value
+        #[structopt(long = "default", parse(try_from_str = parse_key_val))]
+        defaults: Vec<(String, TypedValue)>,
+        /// The labels of the added enum columns, specified as column=label1,label2,...
+        #[structopt(long = "labels", parse(try_from_str = parse_labels))]
+        labels: Vec<(String, Vec<String>)>,
+        /// The digits of the added decimal columns, specified as column=precision,scale
+        #[structopt(long = "precision", parse(try_from_str = parse_precision))]
+        precisions: Vec<(String, proto::Precision)>,
+        /// The added uuid columns to fill in automatically, in the existing rows too,
+        /// specified as column=version where version is one of: v4, v7
+        #[structopt(long = "generate", parse(try_from_str = parse_uuid_version))]
+        generated: Vec<(String, proto::UuidVersion)>,
     },
 }
 
@@ -100,18 +211,54 @@ where
     Ok((s[..pos].to_string(), s[pos + 1..].try_into()?))
 }
 
+/// Parse the labels of an enum column
+fn parse_labels(s: &str) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let (column, labels): (String, String) = parse_key_val(s)?;
+    Ok((column, labels.split(',').map(String::from).collect()))
+}
+
+/// Parse the precision and the optional scale of a decimal column
+fn parse_precision(s: &str) -> Result<(String, proto::Precision), Box<dyn Error>> {
+    let (column, digits): (String, String) = parse_key_val(s)?;
+    let (precision, scale) = digits.split_once(',').unwrap_or((&digits, "0"));
+    Ok((
+        column,
+        proto::Precision { precision: precision.parse()?, scale: scale.parse()? },
+    ))
+}
+
+/// Parse the version of the uuids generated for a column
+fn parse_uuid_version(s: &str) -> Result<(String, proto::UuidVersion), Box<dyn Error>> {
+    let (column, version): (String, String) = parse_key_val(s)?;
+    match version.as_str() {
+        "v4" => Ok((column, proto::UuidVersion::V4)),
+        "v7" => Ok((column, proto::UuidVersion::V7)),
+        _ => Err(format!("invalid uuid version: `{}`", version).into()),
+    }
+}
+
 impl From<Command> for proto::Query {
     fn from(command: Command) -> Self {
         let convert = |values: Vec<(String, TypedValue)>| {
             values.into_iter().map(|(k, v)| (k, v.into())).collect()
         };
+        // compared like in query strings, where `doc.age=42` is a number
+        let conditions_of = |conditions: Vec<(String, TypedValue)>| {
+            conditions
+                .into_iter()
+                .map(|(k, v)| {
+                    let v = json_path_operand(&k, v);
+                    (k, v.into())
+                })
+                .collect()
+        };
 
         match command {
             Command::Select { table, columns, conditions } => proto::Query {
                 query: Some(proto::query::Query::Select(proto::Select {
                     from: table,
                     columns,
-                    conditions: convert(conditions),
+                    conditions: conditions_of(conditions),
                 })),
             },
             Command::Insert { table, values } => proto::Query {
@@ -120,32 +267,119 @@ impl From<Command> for proto::Query {
                     values: convert(values),
                 })),
             },
-            Command::Update { table, values, conditions } => proto::Query {
+            Command::Update { table, values, conditions, all } => proto::Query {
                 query: Some(proto::query::Query::Update(proto::Update {
                     table,
                     set: convert(values),
-                    conditions: convert(conditions),
+                    conditions: conditions_of(conditions),
+                    all,
                 })),
             },
-            Command::Delete { table, conditions } => proto::Query {
+            Command::Delete { table, conditions, all } => proto::Query {
                 query: Some(proto::query::Query::Delete(proto::Delete {
                     from: table,
-                    conditions: convert(conditions),
+                    conditions: conditions_of(conditions),
+                    all,
+                })),
+            },
+            Command::Deleted { table, conditions } => proto::Query {
+                query: Some(proto::query::Query::ListDeleted(proto::ListDeleted {
+                    from: table,
+                    conditions: conditions_of(conditions),
+                })),
+            },
+            Command::Undelete { table, conditions, all } => proto::Query {
+                query: Some(proto::query::Query::Undelete(proto::Undelete {
+                    from: table,
+                    conditions: conditions_of(conditions),
+                    all,
                 })),
             },
             Command::Drop { table } => proto::Query {
                 query: Some(proto::query::Query::Drop(proto::Drop { table })),
             },
-            Command::Create { table, columns } => proto::Query {
+            Command::Undrop { table } => proto::Query {
+                query: Some(proto::query::Query::Undrop(proto::Undrop { table })),
+            },
+            Command::Trash => proto::Query {
+                query: Some(proto::query::Query::ListTrash(proto::ListTrash {})),
+            },
+            Command::Purge { table } => proto::Query {
+                query: Some(proto::query::Query::Purge(proto::Purge {
+                    table: table.unwrap_or_default(),
+                })),
+            },
+            Command::Create {
+                table,
+                columns,
+                auto_increment,
+                defaults,
+                labels,
+                precisions,
+                generated,
+                rules,
+                references,
+            } => proto::Query {
                 query: Some(proto::query::Query::Create(proto::Create {
                     table,
                     columns: columns.into_iter().map(|(k, v)| (k, v as i32)).collect(),
+                    auto_increment: auto_increment.unwrap_or_default(),
+                    defaults: convert(defaults),
+                    rules: rules
+                        .into_iter()
+                        .fold(HashMap::new(), |mut rules, (column, rule)| {
+                            let column_rules: &mut proto::Rules = rules.entry(column).or_default();
+                            column_rules.rules.push(rule.into());
+                            rules
+                        }),
+                    references: references.into_iter().map(|(k, v)| (k, v.into())).collect(),
+                    labels: labels
+                        .into_iter()
+                        .map(|(column, labels)| (column, proto::Labels { labels }))
+                        .collect(),
+                    precisions: precisions.into_iter().collect(),
+                    generated: generated
+                        .into_iter()
+                        .map(|(column, version)| (column, version as i32))
+                        .collect(),
                 })),
             },
-            Command::Rename { table, columns } => proto::Query {
+            Command::Rename { table, to: Some(to), .. } => proto::Query {
+                query: Some(proto::query::Query::Rename(proto::Rename { table, to })),
+            },
+            Command::Rename { table, columns, to: None } => proto::Query {
                 query: Some(proto::query::Query::Alter(proto::Alter {
                     table,
                     rename: columns.into_iter().collect(),
+                    ..Default::default()
+                })),
+            },
+            Command::Alter {
+                table,
+                add,
+                drop,
+                retype,
+                defaults,
+                labels,
+                precisions,
+                generated,
+            } => proto::Query {
+                query: Some(proto::query::Query::Alter(proto::Alter {
+                    table,
+                    add: add.into_iter().map(|(k, v)| (k, v as i32)).collect(),
+                    drop,
+                    retype: retype.into_iter().map(|(k, v)| (k, v as i32)).collect(),
+                    defaults: convert(defaults),
+                    labels: labels
+                        .into_iter()
+                        .map(|(column, labels)| (column, proto::Labels { labels }))
+                        .collect(),
+                    precisions: precisions.into_iter().collect(),
+                    generated: generated
+                        .into_iter()
+                        .map(|(column, version)| (column, version as i32))
+                        .collect(),
+                    ..Default::default()
                 })),
             },
         }
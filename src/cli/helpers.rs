@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 
 use colored::Colorize;
 use rustyline::{
@@ -8,19 +9,35 @@ use rustyline::{
 };
 use rustyline_derive::{Completer, Helper, Validator};
 
-const COMMANDS: [&str; 8] = [
-    "help", "select", "insert", "update", "delete", "create", "drop", "rename",
+const COMMANDS: [&str; 14] = [
+    "help", "select", "insert", "update", "delete", "deleted", "undelete", "create", "drop",
+    "undrop", "trash", "purge", "rename", "alter",
 ];
 
-const FLAGS: [&str; 9] = [
+const FLAGS: [&str; 24] = [
     "-t",
     "-w",
     "-c",
     "-v",
+    "-a",
+    "-d",
+    "-r",
     "--table",
     "--where",
     "--columns",
     "--values",
+    "--add",
+    "--drop",
+    "--retype",
+    "--default",
+    "--to",
+    "--all",
+    "--auto-increment",
+    "--rule",
+    "--reference",
+    "--labels",
+    "--precision",
+    "--generate",
     "--help",
 ];
 
@@ -28,6 +45,8 @@ const FLAGS: [&str; 9] = [
 pub struct DobbyHelper {
     commands: Vec<String>,
     flags: Vec<String>,
+    /// Labels of the enum columns by table and column, fetched from the server
+    pub labels: HashMap<String, HashMap<String, Vec<String>>>,
 }
 
 impl Default for DobbyHelper {
@@ -35,10 +54,27 @@ impl Default for DobbyHelper {
         Self {
             commands: COMMANDS.map(|s| s.to_string()).to_vec(),
             flags: FLAGS.map(|s| s.to_string()).to_vec(),
+            labels: HashMap::new(),
         }
     }
 }
 
+impl DobbyHelper {
+    /// Labels of the column in the table given with `-t`, or in any table
+    fn column_labels(&self, words: &[&str], column: &str) -> Vec<&String> {
+        let table = words
+            .windows(2)
+            .find(|pair| pair[0] == "-t" || pair[0] == "--table")
+            .map(|pair| pair[1]);
+        self.labels
+            .iter()
+            .filter(|(name, _)| table.is_none_or(|table| table == name.as_str()))
+            .filter_map(|(_, columns)| columns.get(column))
+            .flatten()
+            .collect()
+    }
+}
+
 #[derive(Hash, Debug, PartialEq, Eq, Default)]
 pub struct CommandHint(String);
 
@@ -62,6 +98,18 @@ impl Hinter for DobbyHelper {
 
         let words: Vec<_> = line.split_whitespace().collect();
         let last_word = words[words.len() - 1];
+
+        // complete enum labels in column=label pairs
+        if let Some((column, label)) = last_word.split_once('=') {
+            return self
+                .column_labels(&words, column)
+                .into_iter()
+                .find_map(|hint| {
+                    hint.strip_prefix(label)
+                        .filter(|stripped| !stripped.is_empty())
+                        .map(|stripped| CommandHint(stripped.to_string()))
+                });
+        }
         let hints = if words.len() == 1 {
             self.commands.iter()
         } else {
@@ -1,7 +1,8 @@
 use super::{command::Command, format::Format, helpers::DobbyHelper};
 
-use crate::core::types::ColumnSet;
-use crate::grpc::proto::database_client::DatabaseClient;
+use crate::core::types::{Coercion, ColumnSet};
+use crate::grpc::proto::{database_client::DatabaseClient, schema::Table, SchemaRequest};
+use crate::grpc::COERCION_KEY;
 
 use colored::Colorize;
 use prettytable::{csv, Row, Table as PrettyTable};
@@ -14,18 +15,46 @@ pub struct Repl {
     client: DatabaseClient<Channel>,
     editor: Editor<DobbyHelper>,
     format: Format,
+    /// Sent with every query, if set, instead of the server's
+    coercion: Option<Coercion>,
 }
 
 impl Repl {
-    pub async fn init(address: String, format: Format) -> Self {
+    pub async fn init(address: String, format: Format, coercion: Option<Coercion>) -> Self {
         let mut editor = Editor::<DobbyHelper>::new().expect("Failed to init readline");
         editor.set_helper(Some(DobbyHelper::default()));
-        Self {
+        let mut repl = Self {
             client: DatabaseClient::connect(address)
                 .await
                 .expect("Failed to connect to server"),
             editor,
             format,
+            coercion,
+        };
+        repl.fetch_labels().await;
+        repl
+    }
+
+    /// Fetches the labels of the enum columns for the completions
+    async fn fetch_labels(&mut self) {
+        let Ok(schema) = self.client.schema(Request::new(SchemaRequest {})).await else {
+            return;
+        };
+        let labels = schema
+            .into_inner()
+            .tables
+            .into_iter()
+            .map(|(table, Table { columns })| {
+                let columns = columns
+                    .into_iter()
+                    .filter(|column| !column.labels.is_empty())
+                    .map(|column| (column.name, column.labels))
+                    .collect();
+                (table, columns)
+            })
+            .collect();
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.labels = labels;
         }
     }
 
@@ -94,9 +123,14 @@ impl Repl {
             Command::from_iter_safe(command.split_whitespace()).map_err(|e| e.to_string())?;
 
         // execute the command
+        let mut request = Request::new(command.into());
+        if let Some(coercion) = self.coercion {
+            let coercion = coercion.to_string().parse().unwrap();
+            request.metadata_mut().insert(COERCION_KEY, coercion);
+        }
         let response = self
             .client
-            .execute(Request::new(command.into()))
+            .execute(request)
             .await
             .map_err(|e| format!("{} {}\n", "error:".red().bold(), e.message()))?;
 
@@ -112,7 +146,9 @@ impl Repl {
                     self.editor.add_history_entry(line.as_str());
 
                     // print the response
-                    match self.execute(line).await {
+                    let response = self.execute(line).await;
+                    self.fetch_labels().await;
+                    match response {
                         Ok(response) => {
                             self.print_rows(response);
                             println!();
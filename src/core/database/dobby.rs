@@ -1,14 +1,27 @@
 use crate::core::table::Table;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use super::Schema;
-use crate::core::types::{ColumnSet, DobbyError, Query};
+use super::{convert, now, trash_row, Page, Schema, DEFAULT_RETENTION};
+use crate::core::types::{ColumnSet, DataType, DobbyError, OnDelete, Query, TypedValue};
+
+/// Directory inside the database where dropped tables are kept
+const TRASH: &str = ".trash";
+
+/// Bits of a page cursor that hold the offset into the table file,
+/// the rest hold the generation of the file
+const OFFSET_BITS: u32 = 48;
 
 #[derive(Debug)]
 pub struct Dobby {
     tables: HashMap<String, Table>,
+    /// How many times each table file has been replaced or moved, so that
+    /// cursors into the old file aren't used on the new one
+    generations: HashMap<String, u16>,
     path: PathBuf,
+    retention: Duration,
     pub schema: Schema,
 }
 
@@ -18,25 +31,278 @@ impl Dobby {
             Query::Select { from, columns, conditions } => {
                 self.table(&from)?.select(columns, conditions)
             }
-            Query::Insert { into, values } => self.table(&into)?.insert(values).map(|v| vec![v]),
-            Query::Update { table, set, conditions } => self.table(&table)?.update(set, conditions),
-            Query::Delete { from, conditions } => self.table(&from)?.delete(conditions),
-            Query::Create { table, columns } => {
-                self.schema.create_table(table, columns).map(|_| vec![])
+            Query::Insert { into, mut values } => {
+                if let Some((column, next)) = self.schema.auto_increment(&into) {
+                    values.entry(column).or_insert(next.into());
+                }
+                self.schema.fill_defaults(&into, &mut values);
+                let values = self.schema.coerce(&into, values)?;
+                self.schema.check_auto_increment(&into, &values)?;
+                self.check_unique_key(&into, &values, None)?;
+                self.schema.check_rules(&into, &values)?;
+                self.check_references(&into, &values)?;
+                let row = self.table(&into)?.insert(values)?;
+                self.schema.advance_auto_increment(&into, &row);
+                Ok(vec![row])
+            }
+            Query::Update { table, set, conditions, .. } => {
+                let set = self.schema.coerce(&table, set)?;
+                self.schema.check_rules(&table, &set)?;
+                self.check_references(&table, &set)?;
+                if let Some((key, _)) = self.schema.auto_increment(&table) {
+                    if set.contains_key(&key) {
+                        self.schema.check_auto_increment(&table, &set)?;
+                        self.check_unique_key(&table, &set, Some(conditions.clone()))?;
+                        // a row that keeps its key keeps the rows referencing it
+                        let keys = self
+                            .keys(&table, &key, conditions.clone())?
+                            .into_iter()
+                            .filter(|changed| Some(changed) != set.get(&key))
+                            .collect();
+                        self.check_delete(&table, keys, true, &mut HashSet::new())?;
+                    }
+                }
+                let updated = self.table(&table)?.update(set.clone(), conditions)?;
+                if !updated.is_empty() {
+                    self.schema.advance_auto_increment(&table, &set);
+                }
+                Ok(updated)
+            }
+            Query::Delete { from, conditions, .. } => {
+                if let Some((key, _)) = self.schema.auto_increment(&from) {
+                    let keys = self.keys(&from, &key, conditions.clone())?;
+                    let mut visited = keys
+                        .iter()
+                        .map(|key| (from.clone(), key.to_string()))
+                        .collect();
+                    self.check_delete(&from, keys, false, &mut visited)?;
+                }
+                self.delete_rows(&from, conditions)
+            }
+            Query::ListDeleted { from, conditions } => self.table(&from)?.deleted(conditions),
+            Query::Undelete { from, conditions, .. } => {
+                let deleted = self.table(&from)?.deleted(conditions.clone())?;
+                self.check_undelete(&from, &deleted)?;
+                let restored = self.table(&from)?.undelete(conditions)?;
+                for row in &restored {
+                    self.schema.advance_auto_increment(&from, row);
+                }
+                Ok(restored)
+            }
+            Query::Create {
+                table,
+                columns,
+                auto_increment,
+                defaults,
+                rules,
+                references,
+                labels,
+                precisions,
+                generated,
+            } => {
+                let mut schema = self.schema.clone();
+                schema.create_table(table.clone(), columns)?;
+                if let Some(column) = auto_increment {
+                    schema.set_auto_increment(&table, &column)?;
+                }
+                schema.set_labels(&table, labels)?;
+                schema.set_precisions(&table, precisions)?;
+                schema.set_generated(&table, generated)?;
+                schema.set_rules(&table, rules)?;
+                schema.set_references(&table, references)?;
+                schema.set_defaults(&table, defaults)?;
+                self.schema = schema;
+                Ok(vec![])
             }
             Query::Drop { table } => {
-                self.table(&table)?.drop()?;
+                if !self.schema.tables.contains_key(&table) {
+                    return Err(DobbyError::TableNotFound(table));
+                }
+                self.purge_expired()?;
+
+                // the file is moved only once the schema allows it,
+                // and the schema changed only once the file is moved
+                let mut schema = self.schema.clone();
+                schema.trash_table(table.clone(), now())?;
+                let path = self.path.join(&table);
+                if path.exists() {
+                    std::fs::create_dir_all(self.path.join(TRASH))?;
+                    std::fs::rename(path, self.path.join(TRASH).join(&table))?;
+                }
                 self.tables.remove(&table);
-                self.schema.drop_table(table).map(|_| vec![])
+                self.next_generation(&table);
+                self.schema = schema;
+                Ok(vec![])
             }
-            Query::Alter { table, rename } => {
-                self.schema.alter_table(table.clone(), rename)?;
+            Query::Undrop { table } => {
+                self.purge_expired()?;
+                let mut schema = self.schema.clone();
+                schema.restore_table(table.clone())?;
+                let path = self.path.join(TRASH).join(&table);
+                if path.exists() {
+                    std::fs::rename(path, self.path.join(&table))?;
+                }
+                self.next_generation(&table);
+                self.schema = schema;
+                Ok(vec![])
+            }
+            Query::ListTrash => {
+                self.purge_expired()?;
+                let mut trash: Vec<_> = self.schema.trash.iter().collect();
+                trash.sort_by_key(|(table, _)| table.as_str());
+                Ok(trash
+                    .into_iter()
+                    .map(|(table, dropped)| trash_row(table, dropped, self.retention))
+                    .collect())
+            }
+            Query::Purge { table } => {
+                let tables = match table {
+                    Some(table) => vec![table],
+                    None => self.schema.trash.keys().cloned().collect(),
+                };
+                tables.into_iter().map(|table| self.purge(table)).collect()
+            }
+            Query::Rename { table, to } => {
+                let mut schema = self.schema.clone();
+                schema.rename_table(table.clone(), to.clone())?;
+                let path = self.path.join(&table);
+                if path.exists() {
+                    std::fs::rename(path, self.path.join(&to))?;
+                }
+                self.schema = schema;
+                self.next_generation(&table);
+                self.next_generation(&to);
+                if let Some(mut cached) = self.tables.remove(&table) {
+                    cached.name = to.clone();
+                    self.tables.insert(to, cached);
+                }
+                Ok(vec![])
+            }
+            Query::Alter {
+                table,
+                rename,
+                add,
+                drop,
+                retype,
+                defaults,
+                labels,
+                precisions,
+                generated,
+            } => {
+                let mut schema = self.schema.clone();
+                schema.drop_columns(table.clone(), drop.clone())?;
+                schema.retype_columns(table.clone(), retype.clone())?;
+                schema.alter_table(table.clone(), rename.clone())?;
+                schema.add_columns(
+                    table.clone(),
+                    add.clone(),
+                    defaults,
+                    labels,
+                    precisions,
+                    generated,
+                )?;
+                if !add.is_empty() && !self.table(&table)?.is_empty()? {
+                    schema.check_added_columns(&table, &add)?;
+                }
+
+                if !retype.is_empty() {
+                    let mut failed = Vec::new();
+                    for row in self.table(&table)?.rows(vec![], [].into())? {
+                        let row = row?;
+                        if convert(&row, &retype).is_err() {
+                            failed.push(row);
+                        }
+                    }
+                    // deleted rows have to be converted too, to be restored later
+                    for row in self.table(&table)?.deleted([].into())? {
+                        if convert(&row, &retype).is_err() {
+                            failed.push(row);
+                        }
+                    }
+                    if !failed.is_empty() {
+                        return Err(DobbyError::ConversionFailed(failed));
+                    }
+                }
+
+                if !add.is_empty() || !drop.is_empty() || !retype.is_empty() {
+                    let columns = schema.tables[&table].clone();
+                    let labels = schema.labels(&table);
+                    self.rewrite_table(&table, columns, labels, |row| {
+                        let mut row: ColumnSet = convert(&row, &retype)?
+                            .into_iter()
+                            .filter(|(column, _)| !drop.contains(column))
+                            .map(|(column, value)| match rename.get(&column) {
+                                Some(renamed) => (renamed.clone(), value),
+                                None => (column, value),
+                            })
+                            .collect();
+                        schema.fill_defaults(&table, &mut row);
+                        Ok(row)
+                    })?;
+                }
+
+                self.schema = schema;
                 self.update_colunms(table);
                 Ok(vec![])
             }
         }
     }
 
+    pub fn stream(
+        &mut self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        match query {
+            Query::Select { from, columns, conditions } => {
+                for row in self.table(&from)?.rows(columns, conditions)? {
+                    if !sink(row?) {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            query => {
+                for row in self.execute(query)? {
+                    if !sink(row) {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads up to `limit` selected rows from the `cursor`, an offset into the table file
+    /// along with the generation of the file it was taken from
+    pub fn page(
+        &mut self,
+        query: Query,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Result<Page, DobbyError> {
+        let Query::Select { from, columns, conditions } = query else {
+            return Ok(Page { rows: self.execute(query)?, next: None });
+        };
+        let generation = self.generations.get(&from).copied().unwrap_or_default();
+        let offset = match cursor {
+            None => 0,
+            Some(cursor) if cursor >> OFFSET_BITS == generation as u64 => {
+                cursor & ((1 << OFFSET_BITS) - 1)
+            }
+            // the file was replaced, so the offset may point anywhere in it
+            Some(_) => return Err(DobbyError::TableChanged(from)),
+        };
+        let mut rows = self.table(&from)?.rows_from(columns, conditions, offset)?;
+        let page = rows.by_ref().take(limit).collect::<Result<Vec<_>, _>>()?;
+        let next = if page.len() == limit {
+            Some((generation as u64) << OFFSET_BITS | rows.offset()?)
+        } else {
+            None
+        };
+        Ok(Page { rows: page, next })
+    }
+
     pub fn open(path: PathBuf) -> Self {
         log::info!("Opening database at {:?}", path);
         if !path.is_dir() {
@@ -44,7 +310,13 @@ impl Dobby {
         }
         let schema = Schema::load(&path);
         assert!(schema.is_dobby(), "Wrong schema type");
-        Dobby { tables: HashMap::new(), schema, path }
+        Dobby {
+            tables: HashMap::new(),
+            generations: HashMap::new(),
+            retention: DEFAULT_RETENTION,
+            schema,
+            path,
+        }
     }
 
     pub fn create(path: PathBuf, name: String) -> Self {
@@ -56,11 +328,192 @@ impl Dobby {
 
         Dobby {
             tables: HashMap::new(),
+            generations: HashMap::new(),
+            retention: DEFAULT_RETENTION,
             schema: Schema::new_dobby(name),
             path,
         }
     }
 
+    /// Sets how long dropped tables can be restored
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Deletes the dropped table's file for good
+    fn purge(&mut self, table: String) -> Result<ColumnSet, DobbyError> {
+        let dropped = self.schema.purge_table(table.clone())?;
+        let path = self.path.join(TRASH).join(&table);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(trash_row(&table, &dropped, self.retention))
+    }
+
+    fn purge_expired(&mut self) -> Result<(), DobbyError> {
+        for table in self.schema.expired_tables(self.retention, now()) {
+            self.purge(table)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the referencing values match rows of the referenced tables
+    fn check_references(&mut self, table: &str, values: &ColumnSet) -> Result<(), DobbyError> {
+        for (column, value) in values {
+            if let Some(reference) = self.schema.column_attributes(table, column).references {
+                let conditions = [(reference.column, value.clone())].into();
+                let referenced = self
+                    .table(&reference.table)?
+                    .rows(vec![], conditions)?
+                    .next();
+                if referenced.transpose()?.is_none() {
+                    return Err(DobbyError::MissingReference(
+                        value.clone(),
+                        column.clone(),
+                        reference.table,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the key value the values give is taken by no other row: an inserted row,
+    /// or the rows an update with the `conditions` changes, of which there can be only one
+    fn check_unique_key(
+        &mut self,
+        table: &str,
+        values: &ColumnSet,
+        conditions: Option<ColumnSet>,
+    ) -> Result<(), DobbyError> {
+        let Some((key, _)) = self.schema.auto_increment(table) else {
+            return Ok(());
+        };
+        let Some(value) = values.get(&key) else {
+            return Ok(());
+        };
+        let taken = !self
+            .keys(table, &key, [(key.clone(), value.clone())].into())?
+            .is_empty();
+        let duplicate = match conditions {
+            None => taken,
+            Some(conditions) => match &self.keys(table, &key, conditions)?[..] {
+                [] => false,
+                // a row keeps its own key
+                [updated] => taken && updated != value,
+                _ => true,
+            },
+        };
+        if duplicate {
+            return Err(DobbyError::DuplicateKey(
+                value.clone(),
+                key,
+                table.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the deleted rows can be restored, like they were inserted again:
+    /// the table's rules and references may have changed since they were deleted,
+    /// and their keys taken by other rows. They can reference each other, though.
+    fn check_undelete(&mut self, table: &str, rows: &[ColumnSet]) -> Result<(), DobbyError> {
+        let key = self.schema.auto_increment(table).map(|(key, _)| key);
+        let mut restored = HashSet::new();
+        if let Some(key) = &key {
+            for row in rows {
+                if !restored.insert(row[key].to_string()) {
+                    return Err(DobbyError::DuplicateKey(
+                        row[key].clone(),
+                        key.clone(),
+                        table.to_string(),
+                    ));
+                }
+            }
+        }
+        for row in rows {
+            self.schema.check_rules(table, row)?;
+            self.check_unique_key(table, row, None)?;
+            let references = row
+                .iter()
+                .filter(|(column, value)| {
+                    let reference = self.schema.column_attributes(table, column).references;
+                    !matches!(reference, Some(reference)
+                        if reference.table == table && restored.contains(&value.to_string()))
+                })
+                .map(|(column, value)| (column.clone(), value.clone()))
+                .collect();
+            self.check_references(table, &references)?;
+        }
+        Ok(())
+    }
+
+    /// Values of the key column in the rows that match the conditions
+    fn keys(
+        &mut self,
+        table: &str,
+        key: &str,
+        conditions: ColumnSet,
+    ) -> Result<Vec<TypedValue>, DobbyError> {
+        self.table(table)?
+            .rows(vec![key.to_string()], conditions)?
+            .map(|row| row.map(|mut row| row.remove(key).unwrap()))
+            .collect()
+    }
+
+    /// Checks that the rows with the keys can be deleted: the rows referencing them
+    /// have to be deleted along with them, unless `restrict` is set.
+    /// `visited` holds the rows that are already known to be deleted.
+    fn check_delete(
+        &mut self,
+        table: &str,
+        keys: Vec<TypedValue>,
+        restrict: bool,
+        visited: &mut HashSet<(String, String)>,
+    ) -> Result<(), DobbyError> {
+        for (child, column, reference) in self.schema.references_to(table) {
+            let child_key = self.schema.auto_increment(&child).map(|(key, _)| key);
+            for key in &keys {
+                let conditions: ColumnSet = [(column.clone(), key.clone())].into();
+                if restrict || reference.on_delete == OnDelete::Restrict {
+                    let referencing = self.table(&child)?.rows(vec![], conditions)?.next();
+                    if referencing.transpose()?.is_some() {
+                        return Err(DobbyError::Referenced(table.to_string(), child));
+                    }
+                } else if let Some(child_key) = &child_key {
+                    let keys = self
+                        .keys(&child, child_key, conditions)?
+                        .into_iter()
+                        .filter(|key| visited.insert((child.clone(), key.to_string())))
+                        .collect();
+                    self.check_delete(&child, keys, false, visited)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the rows along with the rows referencing them with `OnDelete::Cascade`
+    fn delete_rows(
+        &mut self,
+        table: &str,
+        conditions: ColumnSet,
+    ) -> Result<Vec<ColumnSet>, DobbyError> {
+        let deleted = self.table(table)?.delete(conditions)?;
+        if let Some((key, _)) = self.schema.auto_increment(table) {
+            for (child, column, reference) in self.schema.references_to(table) {
+                if reference.on_delete != OnDelete::Cascade {
+                    continue;
+                }
+                for row in &deleted {
+                    self.delete_rows(&child, [(column.clone(), row[&key].clone())].into())?;
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
     fn table(&mut self, name: &str) -> Result<&mut Table, DobbyError> {
         if !self.schema.tables.contains_key(name) {
             return Err(DobbyError::TableNotFound(name.to_string()));
@@ -68,17 +521,56 @@ impl Dobby {
 
         if !self.tables.contains_key(name) {
             let columns = self.schema.tables[name].clone();
-            let table = Table::open(name.to_string(), columns, &self.path);
+            let labels = self.schema.labels(name);
+            let table = Table::open(name.to_string(), columns, labels, &self.path);
             self.tables.insert(name.to_string(), table);
         }
 
         Ok(self.tables.get_mut(name).unwrap())
     }
 
+    /// Rewrites the table file through a temporary one, so that a failed
+    /// conversion leaves the table untouched
+    fn rewrite_table<F>(
+        &mut self,
+        name: &str,
+        columns: Vec<(String, DataType)>,
+        labels: HashMap<String, Vec<String>>,
+        convert: F,
+    ) -> Result<(), DobbyError>
+    where
+        F: FnMut(ColumnSet) -> Result<ColumnSet, DobbyError>,
+    {
+        let path = self.path.join(name);
+        let tmp_path = self.path.join(format!("{}.tmp", name));
+        let table = self.table(name)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        if let Err(err) = table.rewrite(columns, labels, file, convert) {
+            std::fs::remove_file(&tmp_path)?;
+            return Err(err);
+        }
+        std::fs::rename(tmp_path, path)?;
+        self.next_generation(name);
+        Ok(())
+    }
+
+    /// Invalidates the cursors into the current file of the table
+    fn next_generation(&mut self, table: &str) {
+        let generation = self.generations.entry(table.to_string()).or_default();
+        *generation = generation.wrapping_add(1);
+    }
+
     fn update_colunms(&mut self, table: String) {
-        self.tables
-            .entry(table.clone())
-            .and_modify(|e| e.columns = self.schema.tables[&table].clone());
+        self.tables.entry(table.clone()).and_modify(|e| {
+            e.columns = self.schema.tables[&table].clone();
+            e.labels = self.schema.labels(&table);
+        });
     }
 }
 
@@ -1,13 +1,311 @@
-use super::schema::Schema;
-use super::types::{ColumnSet, DobbyError, Query};
-use std::sync::Mutex;
+use super::schema::{DroppedTable, Schema};
+use super::types::{Coercion, ColumnSet, DataType, DobbyError, Query};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::mpsc;
 
 pub mod dobby;
+#[cfg(feature = "sqlite")]
 pub mod sqlite;
 
+#[cfg(test)]
+mod tests;
+
+/// How long dropped tables are kept in the trash by default
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How many rows a streaming query can get ahead of its consumer,
+/// which is also how many it reads at a time
+const STREAM_BUFFER: usize = 64;
+
+/// Rows of a query read at once, along with where to read the next ones from
+pub struct Page {
+    pub rows: Vec<ColumnSet>,
+    /// Where the rest of the rows start - an offset into the table file, tagged with its
+    /// generation, for the native engine, the last rowid for SQLite - or `None` if there are no more
+    pub next: Option<u64>,
+}
+
 pub trait Database: Send + Sync {
     fn execute(&self, query: Query) -> Result<Vec<ColumnSet>, DobbyError>;
+    /// Executes the query, feeding the resulting rows into `sink` one by one
+    /// until it returns `false`
+    fn stream(
+        &self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError>;
+    /// Executes the query, returning up to `limit` of the selected rows from the `cursor`
+    /// of the previous page on. Other queries return all of their rows at once.
+    fn page(&self, query: Query, cursor: Option<u64>, limit: usize) -> Result<Page, DobbyError>;
     fn schema(&self) -> Schema;
+    /// Columns of the table, if there is one, without cloning the whole schema
+    fn columns(&self, table: &str) -> Option<Vec<(String, DataType)>>;
+}
+
+/// Safety mode for a database: rejects UPDATE, DELETE and UNDELETE queries without
+/// conditions, unless they explicitly target all rows
+pub struct SafeMode(pub Arc<dyn Database>);
+
+impl SafeMode {
+    fn check(query: &Query) -> Result<(), DobbyError> {
+        match query {
+            Query::Update { table, conditions, all: false, .. }
+            | Query::Delete { from: table, conditions, all: false }
+            | Query::Undelete { from: table, conditions, all: false } => {
+                if conditions.is_empty() {
+                    return Err(DobbyError::UnfilteredQuery(table.clone()));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Database for SafeMode {
+    fn execute(&self, query: Query) -> Result<Vec<ColumnSet>, DobbyError> {
+        Self::check(&query)?;
+        self.0.execute(query)
+    }
+
+    fn stream(
+        &self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        Self::check(&query)?;
+        self.0.stream(query, sink)
+    }
+
+    fn page(&self, query: Query, cursor: Option<u64>, limit: usize) -> Result<Page, DobbyError> {
+        Self::check(&query)?;
+        self.0.page(query, cursor, limit)
+    }
+
+    fn schema(&self) -> Schema {
+        self.0.schema()
+    }
+
+    fn columns(&self, table: &str) -> Option<Vec<(String, DataType)>> {
+        self.0.columns(table)
+    }
+}
+
+/// Coercion mode for a database: converts the values of the queries to the datatypes
+/// of their columns by the rules the coercion allows, before the database coerces
+/// them the lenient way. The last field tells whether the conditions are text, like in
+/// query strings, so that they are only held to the coercion when it's more lenient.
+pub struct Coerced(pub Arc<dyn Database>, pub Coercion, pub bool);
+
+impl Coerced {
+    fn coerce(&self, query: Query) -> Result<Query, DobbyError> {
+        let Coerced(db, coercion, text_conditions) = self;
+        let conditions_coercion = if *text_conditions {
+            (*coercion).max(Coercion::Lenient)
+        } else {
+            *coercion
+        };
+        let columns = match &query {
+            Query::Insert { into: table, .. }
+            | Query::Update { table, .. }
+            | Query::Select { from: table, .. }
+            | Query::Delete { from: table, .. }
+            | Query::ListDeleted { from: table, .. }
+            | Query::Undelete { from: table, .. } => db.columns(table).unwrap_or_default(),
+            _ => vec![],
+        };
+        let query = match query {
+            Query::Insert { into, values } => {
+                let values = coerce_values(&columns, values, *coercion)?;
+                Query::Insert { into, values }
+            }
+            Query::Update { table, set, conditions, all } => {
+                let set = coerce_values(&columns, set, *coercion)?;
+                let conditions = coerce_values(&columns, conditions, conditions_coercion)?;
+                Query::Update { table, set, conditions, all }
+            }
+            Query::Select { from, columns: selected, conditions } => {
+                let conditions = coerce_values(&columns, conditions, conditions_coercion)?;
+                Query::Select { from, columns: selected, conditions }
+            }
+            Query::Delete { from, conditions, all } => {
+                let conditions = coerce_values(&columns, conditions, conditions_coercion)?;
+                Query::Delete { from, conditions, all }
+            }
+            Query::ListDeleted { from, conditions } => {
+                let conditions = coerce_values(&columns, conditions, conditions_coercion)?;
+                Query::ListDeleted { from, conditions }
+            }
+            Query::Undelete { from, conditions, all } => {
+                let conditions = coerce_values(&columns, conditions, conditions_coercion)?;
+                Query::Undelete { from, conditions, all }
+            }
+            Query::Create {
+                table,
+                columns,
+                auto_increment,
+                defaults,
+                rules,
+                references,
+                labels,
+                precisions,
+                generated,
+            } => {
+                let defaults = coerce_values(&columns, defaults, *coercion)?;
+                Query::Create {
+                    table,
+                    columns,
+                    auto_increment,
+                    defaults,
+                    rules,
+                    references,
+                    labels,
+                    precisions,
+                    generated,
+                }
+            }
+            Query::Alter {
+                table,
+                rename,
+                add,
+                drop,
+                retype,
+                defaults,
+                labels,
+                precisions,
+                generated,
+            } => {
+                let defaults = coerce_values(&add, defaults, *coercion)?;
+                Query::Alter {
+                    table,
+                    rename,
+                    add,
+                    drop,
+                    retype,
+                    defaults,
+                    labels,
+                    precisions,
+                    generated,
+                }
+            }
+            query => query,
+        };
+        Ok(query)
+    }
+}
+
+impl Database for Coerced {
+    fn execute(&self, query: Query) -> Result<Vec<ColumnSet>, DobbyError> {
+        self.0.execute(self.coerce(query)?)
+    }
+
+    fn stream(
+        &self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        self.0.stream(self.coerce(query)?, sink)
+    }
+
+    fn page(&self, query: Query, cursor: Option<u64>, limit: usize) -> Result<Page, DobbyError> {
+        self.0.page(self.coerce(query)?, cursor, limit)
+    }
+
+    fn schema(&self) -> Schema {
+        self.0.schema()
+    }
+
+    fn columns(&self, table: &str) -> Option<Vec<(String, DataType)>> {
+        self.0.columns(table)
+    }
+}
+
+/// Coerces the values of the columns, leaving the rest, like unknown columns
+/// and parts of JSON documents, to the database
+fn coerce_values(
+    columns: &[(String, DataType)],
+    values: ColumnSet,
+    coercion: Coercion,
+) -> Result<ColumnSet, DobbyError> {
+    values
+        .into_iter()
+        .map(
+            |(column, value)| match columns.iter().find(|(name, _)| name == &column) {
+                Some((_, data_type)) => Ok((column, value.coerce_with(*data_type, coercion)?)),
+                None => Ok((column, value)),
+            },
+        )
+        .collect()
+}
+
+/// Streams the query results through a bounded channel from a blocking thread.
+/// An error, if any, is sent as the last message.
+///
+/// The rows are read a page at a time, and the database is only locked while a page
+/// is read, so a slow consumer holds up neither the database nor more than
+/// two pages of rows.
+pub fn stream_rows(
+    db: Arc<dyn Database>,
+    query: Query,
+) -> mpsc::Receiver<Result<ColumnSet, DobbyError>> {
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let mut cursor = None;
+        loop {
+            let page = match db.page(query.clone(), cursor, STREAM_BUFFER) {
+                Ok(page) => page,
+                Err(err) => {
+                    sender.blocking_send(Err(err)).ok();
+                    return;
+                }
+            };
+            for row in page.rows {
+                if sender.blocking_send(Ok(row)).is_err() {
+                    return;
+                }
+            }
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => return,
+            }
+        }
+    });
+    receiver
+}
+
+/// Current unix timestamp, in seconds
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the unix epoch")
+        .as_secs()
+}
+
+/// Describes a dropped table as a row of the trash listing
+fn trash_row(table: &str, dropped: &DroppedTable, retention: Duration) -> ColumnSet {
+    let expires_at = dropped.dropped_at + retention.as_secs();
+    [
+        ("table".into(), table.into()),
+        ("dropped_at".into(), (dropped.dropped_at as i64).into()),
+        ("expires_at".into(), (expires_at as i64).into()),
+    ]
+    .into()
+}
+
+/// Coerces the row's values to the new datatypes
+fn convert(row: &ColumnSet, retype: &HashMap<String, DataType>) -> Result<ColumnSet, DobbyError> {
+    let mut row = row.clone();
+    for (column, data_type) in retype {
+        if let Some(value) = row.remove(column) {
+            let value = value.coerce(*data_type)?;
+            value.validate()?;
+            row.insert(column.clone(), value);
+        }
+    }
+    Ok(row)
 }
 
 impl Database for Mutex<dobby::Dobby> {
@@ -15,17 +313,50 @@ impl Database for Mutex<dobby::Dobby> {
         self.lock().unwrap().execute(query)
     }
 
+    fn stream(
+        &self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        self.lock().unwrap().stream(query, sink)
+    }
+
+    fn page(&self, query: Query, cursor: Option<u64>, limit: usize) -> Result<Page, DobbyError> {
+        self.lock().unwrap().page(query, cursor, limit)
+    }
+
     fn schema(&self) -> Schema {
         self.lock().unwrap().schema.clone()
     }
+
+    fn columns(&self, table: &str) -> Option<Vec<(String, DataType)>> {
+        self.lock().unwrap().schema.tables.get(table).cloned()
+    }
 }
 
+#[cfg(feature = "sqlite")]
 impl Database for Mutex<sqlite::Sqlite> {
     fn execute(&self, query: Query) -> Result<Vec<ColumnSet>, DobbyError> {
         self.lock().unwrap().execute(query)
     }
 
+    fn stream(
+        &self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        self.lock().unwrap().stream(query, sink)
+    }
+
+    fn page(&self, query: Query, cursor: Option<u64>, limit: usize) -> Result<Page, DobbyError> {
+        self.lock().unwrap().page(query, cursor, limit)
+    }
+
     fn schema(&self) -> Schema {
         self.lock().unwrap().schema.clone()
     }
+
+    fn columns(&self, table: &str) -> Option<Vec<(String, DataType)>> {
+        self.lock().unwrap().schema.tables.get(table).cloned()
+    }
 }
@@ -1,12 +1,35 @@
-use crate::core::schema::Schema;
-use crate::core::types::{ColumnSet, DataType, DobbyError, Query, TypedValue};
+use super::{convert, now, trash_row, Page, DEFAULT_RETENTION};
+use crate::core::schema::{Attributes, Schema, RESERVED_COLUMNS};
+use crate::core::types::{
+    split_json_path, ColumnSet, DataType, DobbyError, OnDelete, Query, TypedValue, TIMESTAMP_FORMAT,
+};
+use chrono::NaiveDateTime;
+use rusqlite::types::ValueRef;
 use rusqlite::Connection;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
+use std::time::Duration;
+
+/// Columns of an existing SQLite file that are left out of the schema,
+/// by table and column, with the reasons
+type Unsupported = HashMap<String, HashMap<String, String>>;
+
+/// Text formats of the timestamp columns of an existing SQLite file that aren't dobby's,
+/// by table and column
+type TimestampFormats = HashMap<String, HashMap<String, &'static str>>;
+
+/// Formats of timestamps written by SQLite's `datetime()` and `strftime()`,
+/// which dobby can serve besides its own
+const SQLITE_TIMESTAMP_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M:%S%.3f"];
 
 pub struct Sqlite {
     db: rusqlite::Connection,
-    path: PathBuf,
+    /// Directory of the schema file, if the schema isn't read from the SQLite file itself
+    path: Option<PathBuf>,
+    retention: Duration,
+    unsupported: Unsupported,
+    timestamp_formats: TimestampFormats,
     pub schema: Schema,
 }
 
@@ -26,58 +49,110 @@ impl Query {
                 "WHERE {}",
                 conditions
                     .keys()
-                    .map(|column| format!("{} = ?", column,))
+                    .map(|key| match split_json_path(key) {
+                        // JSON text of the part of the document, compared to the operand's
+                        Some((column, path)) => format!(
+                            "{} -> {} = ?",
+                            quoted(column),
+                            sql_literal(&TypedValue::String(sqlite_json_path(path)))
+                        ),
+                        None => format!("{} = ?", quoted(key)),
+                    })
                     .collect::<Vec<_>>()
                     .join(" AND ")
             )
         }
     }
 
+    fn sql_columns(columns: &[String]) -> String {
+        if columns.is_empty() {
+            "*".into()
+        } else {
+            columns
+                .iter()
+                .map(|c| quoted(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    }
+
+    /// A select of up to `limit` rows past the rowid bound last, in the order of their
+    /// rowids, which come first in the rows
+    fn paged_sql(&self, limit: usize) -> String {
+        let Query::Select { columns, from, .. } = self else {
+            return self.to_sql();
+        };
+        let conditions = self.sql_conditions();
+        format!(
+            "SELECT rowid, {} FROM {} {} rowid > ? ORDER BY rowid LIMIT {}",
+            Self::sql_columns(columns),
+            quoted(from),
+            if conditions.is_empty() {
+                "WHERE".to_string()
+            } else {
+                conditions + " AND"
+            },
+            limit
+        )
+    }
+
     pub fn to_sql(&self) -> String {
         match self {
             Query::Select { columns, from, .. } => {
                 format!(
                     "SELECT {} FROM {} {}",
-                    if columns.is_empty() {
-                        "*".into()
-                    } else {
-                        columns.join(", ")
-                    },
-                    from,
+                    Self::sql_columns(columns),
+                    quoted(from),
                     self.sql_conditions()
                 )
             }
+            Query::Insert { into, values } if values.is_empty() => {
+                format!("INSERT INTO {} DEFAULT VALUES", quoted(into))
+            }
             Query::Insert { into, values } => format!(
                 "INSERT INTO {} ({}) VALUES ({})",
-                into,
+                quoted(into),
                 values
                     .keys()
-                    .map(|k| k.as_str())
+                    .map(|k| quoted(k))
                     .collect::<Vec<_>>()
                     .join(", "),
                 values.keys().map(|_| "?").collect::<Vec<_>>().join(", ")
             ),
-            Query::Update { table, set, .. } => format!(
-                "UPDATE {} SET {} {}",
-                table,
-                set.keys()
-                    .map(|column| format!("{} = ?", column))
-                    .collect::<Vec<_>>()
-                    .join(", "),
-                self.sql_conditions()
-            ),
-            Query::Delete { from, .. } => format!("DELETE FROM {} {}", from, self.sql_conditions()),
-            Query::Drop { table } => format!("DROP TABLE {}", table),
-            Query::Create { table, columns } => format!(
-                "CREATE TABLE {} ({})",
-                table,
-                columns
-                    .iter()
-                    .map(|(name, data_type)| format!("{} {} NOT NULL", name, data_type.to_sql()))
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            ),
-            Query::Alter { .. } => "".into(),
+            Query::Update { table, set, .. } => {
+                let assignments = set.keys().map(|column| format!("{} = ?", quoted(column)));
+                let conditions = self.sql_conditions();
+                // rows that have the values already aren't updated, as in the native engine
+                format!(
+                    "UPDATE {} SET {} {} NOT ({}) RETURNING *",
+                    quoted(table),
+                    assignments.clone().collect::<Vec<_>>().join(", "),
+                    if conditions.is_empty() {
+                        "WHERE".to_string()
+                    } else {
+                        conditions + " AND"
+                    },
+                    assignments.collect::<Vec<_>>().join(" AND ")
+                )
+            }
+            Query::Delete { from, .. } => {
+                format!(
+                    "DELETE FROM {} {} RETURNING *",
+                    quoted(from),
+                    self.sql_conditions()
+                )
+            }
+            Query::Drop { table } => format!("DROP TABLE {}", quoted(table)),
+            Query::Rename { table, to } => {
+                format!("ALTER TABLE {} RENAME TO {}", quoted(table), quoted(to))
+            }
+            Query::Alter { .. }
+            | Query::Create { .. }
+            | Query::Undrop { .. }
+            | Query::ListTrash
+            | Query::Purge { .. }
+            | Query::ListDeleted { .. }
+            | Query::Undelete { .. } => "".into(),
         }
     }
 }
@@ -90,9 +165,104 @@ impl Sqlite {
             panic!("Database not found at {:?}", path);
         }
         let db = Connection::open(&sqlite_path).expect("Failed to open database");
+        enable_foreign_keys(&db);
         let schema = Schema::load(&path);
         assert!(schema.is_sqlite(), "Wrong schema type");
-        Self { db, schema, path }
+        Self {
+            db,
+            schema,
+            path: Some(path),
+            retention: DEFAULT_RETENTION,
+            unsupported: HashMap::new(),
+            timestamp_formats: HashMap::new(),
+        }
+    }
+
+    /// Opens an SQLite file that wasn't created by dobby, reading the schema from the file.
+    /// Tables and columns that dobby can't serve are left out, with a warning.
+    /// The schema isn't stored anywhere, so the attributes only SQLite doesn't know of,
+    /// like rules and labels, are lost when the database is closed, and so are the times
+    /// the tables in the trash were dropped at
+    pub fn introspect(file: PathBuf) -> Self {
+        log::info!("Opening SQLite file at {:?}", file);
+        if !file.is_file() {
+            panic!("Database not found at {:?}", file);
+        }
+        let db = Connection::open(&file).expect("Failed to open database");
+        enable_foreign_keys(&db);
+        let name = file
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (schema, unsupported, timestamp_formats) =
+            read_schema(&db, name).expect("Failed to read schema");
+        let mut tables: Vec<_> = unsupported.iter().collect();
+        tables.sort_by_key(|(table, _)| table.as_str());
+        for (table, columns) in tables {
+            let mut columns: Vec<_> = columns
+                .iter()
+                .map(|(column, reason)| format!("{} ({})", column, reason))
+                .collect();
+            columns.sort();
+            log::warn!(
+                "Columns of table {} left out of the schema: {}",
+                table,
+                columns.join(", ")
+            );
+        }
+        Self {
+            db,
+            schema,
+            path: None,
+            retention: DEFAULT_RETENTION,
+            unsupported,
+            timestamp_formats,
+        }
+    }
+
+    /// Columns of an existing SQLite file that are left out of the schema,
+    /// by table and column, with the reasons
+    pub fn left_out(&self) -> &HashMap<String, HashMap<String, String>> {
+        &self.unsupported
+    }
+
+    /// Moves what's known of the stored columns of a table along with the table itself
+    fn move_table(&mut self, table: &str, to: &str) {
+        if let Some(columns) = self.unsupported.remove(table) {
+            self.unsupported.insert(to.to_string(), columns);
+        }
+        if let Some(formats) = self.timestamp_formats.remove(table) {
+            self.timestamp_formats.insert(to.to_string(), formats);
+        }
+    }
+
+    /// The values as they're bound for the columns of the table, with timestamps in the format
+    /// their column stores them in. Values of conditions that are finer than the format
+    /// are bound as they are, so they match nothing, while stored values can't be rounded
+    fn bound<'a>(
+        &self,
+        table: &str,
+        values: &'a ColumnSet,
+        conditions: bool,
+    ) -> Result<Vec<Cow<'a, TypedValue>>, DobbyError> {
+        let formats = self.timestamp_formats.get(table);
+        values
+            .iter()
+            .map(|(column, value)| {
+                let format = formats.and_then(|formats| formats.get(column));
+                let (Some(format), TypedValue::Timestamp(t)) = (format, value) else {
+                    return Ok(Cow::Borrowed(value));
+                };
+                let text = t.format(format).to_string();
+                match NaiveDateTime::parse_from_str(&text, format) {
+                    Ok(stored) if stored == t.naive_utc() => {
+                        Ok(Cow::Owned(TypedValue::String(text)))
+                    }
+                    _ if conditions => Ok(Cow::Borrowed(value)),
+                    _ => Err(DobbyError::InvalidValue(value.clone(), DataType::Timestamp)),
+                }
+            })
+            .collect()
     }
 
     pub fn create(path: PathBuf, name: String) -> Self {
@@ -103,131 +273,1037 @@ impl Sqlite {
         std::fs::create_dir_all(&path).expect("Failed to create database directory");
         let sqlite_path = path.join("db.sqlite");
         let db = Connection::open(sqlite_path).expect("Failed to open database");
-        Self { db, schema: Schema::new_sqlite(name), path }
+        enable_foreign_keys(&db);
+        Self {
+            db,
+            schema: Schema::new_sqlite(name),
+            path: Some(path),
+            retention: DEFAULT_RETENTION,
+            unsupported: HashMap::new(),
+            timestamp_formats: HashMap::new(),
+        }
     }
 
-    pub fn execute(&mut self, query: Query) -> Result<Vec<ColumnSet>, DobbyError> {
-        match &query {
-            Query::Select { from, conditions, .. } => {
-                let mut stmt = self.db.prepare(&query.to_sql())?;
-                let columns: Vec<_> = stmt
-                    .columns()
+    /// Sets how long dropped tables can be restored
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Drops the table from the trash for good
+    fn purge(&mut self, table: String) -> Result<ColumnSet, DobbyError> {
+        let dropped = self.schema.purge_table(table.clone())?;
+        self.db
+            .execute(&format!("DROP TABLE IF EXISTS {}", trashed(&table)), [])?;
+        self.unsupported.remove(&trash_name(&table));
+        self.timestamp_formats.remove(&trash_name(&table));
+        Ok(trash_row(&table, &dropped, self.retention))
+    }
+
+    fn purge_expired(&mut self) -> Result<(), DobbyError> {
+        for table in self.schema.expired_tables(self.retention, now()) {
+            self.purge(table)?;
+        }
+        Ok(())
+    }
+
+    /// Coerces the values of the query to the types of their columns,
+    /// so that they are bound in their SQLite representation.
+    /// Every column has to be in the schema, like the conditions' keys
+    fn coerce_query(&self, query: &mut Query) -> Result<(), DobbyError> {
+        let (table, values, conditions) = match query {
+            Query::Select { from, columns, conditions } => {
+                self.check_supported(from, columns.iter())?;
+                self.schema.check_columns(from, columns)?;
+                (from, None, Some(conditions))
+            }
+            Query::Delete { from, conditions, .. } => (from, None, Some(conditions)),
+            Query::Update { table, set, conditions, .. } => (table, Some(set), Some(conditions)),
+            Query::Insert { into, values } => (into, Some(values), None),
+            _ => return Ok(()),
+        };
+        let keys = values
+            .iter()
+            .chain(conditions.iter())
+            .flat_map(|set| set.keys());
+        self.check_supported(table, keys)?;
+        if let Some(values) = values {
+            *values = self.schema.coerce(table, std::mem::take(values))?;
+        }
+        if let Some(conditions) = conditions {
+            *conditions = self
+                .schema
+                .coerce_conditions(table, std::mem::take(conditions))?;
+        }
+        Ok(())
+    }
+
+    /// Tells that a column was left out of the schema, rather than that it doesn't exist
+    fn check_supported<'a>(
+        &self,
+        table: &str,
+        mut columns: impl Iterator<Item = &'a String>,
+    ) -> Result<(), DobbyError> {
+        let Some(unsupported) = self.unsupported.get(table) else {
+            return Ok(());
+        };
+        let found = columns.find_map(|key| {
+            let column = split_json_path(key).map_or(key.as_str(), |(column, _)| column);
+            unsupported.get_key_value(column)
+        });
+        match found {
+            Some((column, reason)) => Err(DobbyError::Unsupported(format!(
+                "Column {} of table {} ({})",
+                column, table, reason
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells which key value the query duplicated if it failed on the uniqueness
+    /// of the auto-increment column, with the same error as the native engine
+    fn check_unique_key<T>(
+        &self,
+        query: &Query,
+        result: Result<T, DobbyError>,
+    ) -> Result<T, DobbyError> {
+        let (table, values) = match query {
+            Query::Insert { into, values } => (into, values),
+            Query::Update { table, set, .. } => (table, set),
+            _ => return result,
+        };
+        let Some((key, _)) = self.schema.auto_increment(table) else {
+            return result;
+        };
+        match (&result, values.get(&key)) {
+            (
+                Err(DobbyError::SqlError(rusqlite::Error::SqliteFailure(failure, Some(message)))),
+                Some(value),
+            ) if failure.code == rusqlite::ErrorCode::ConstraintViolation
+                && *message == format!("UNIQUE constraint failed: {}.{}", table, key) =>
+            {
+                Err(DobbyError::DuplicateKey(value.clone(), key, table.clone()))
+            }
+            _ => result,
+        }
+    }
+
+    /// Tells which reference the query broke if it failed on a foreign key,
+    /// since SQLite doesn't say, with the same errors as the native engine.
+    /// The failure's extended code depends on the statement, e.g. with RETURNING,
+    /// so it's told by the message
+    fn check_foreign_keys<T>(
+        &mut self,
+        query: &Query,
+        result: Result<T, DobbyError>,
+    ) -> Result<T, DobbyError> {
+        match &result {
+            Err(DobbyError::SqlError(rusqlite::Error::SqliteFailure(failure, Some(message))))
+                if failure.code == rusqlite::ErrorCode::ConstraintViolation
+                    && message.starts_with("FOREIGN KEY") => {}
+            _ => return result,
+        }
+        let (table, values, conditions) = match query {
+            Query::Insert { into, values } => (into, values.clone(), None),
+            Query::Update { table, set, conditions, .. } => (table, set.clone(), Some(conditions)),
+            Query::Delete { from, conditions, .. } => (from, HashMap::new(), Some(conditions)),
+            _ => return result,
+        };
+        for (column, value) in values.iter() {
+            if let Some(reference) = self.schema.column_attributes(table, column).references {
+                let conditions = [(reference.column, value.clone())].into();
+                if self
+                    .select(&reference.table, vec![], conditions)?
+                    .is_empty()
+                {
+                    return Err(DobbyError::MissingReference(
+                        value.clone(),
+                        column.clone(),
+                        reference.table,
+                    ));
+                }
+            }
+        }
+        // the referenced keys are changed or deleted
+        if let (Some(conditions), Some((key, _))) = (conditions, self.schema.auto_increment(table))
+        {
+            let restrict = matches!(query, Query::Update { .. });
+            if !restrict || values.contains_key(&key) {
+                let keys = self.keys(table, &key, conditions.clone())?;
+                let mut visited = keys
                     .iter()
-                    .map(|c| {
-                        let name = c.name().to_string();
-                        let data_type = match c.decl_type() {
-                            Some("INTEGER") => DataType::Int,
-                            Some("TEXT") => DataType::String,
-                            Some("REAL") => DataType::Float,
-                            _ => unreachable!(),
-                        };
-                        let index = stmt.column_index(&name).unwrap();
-                        (name, data_type, index)
-                    })
+                    .map(|key| (table.clone(), key.to_string()))
                     .collect();
+                self.check_delete(table, keys, restrict, &mut visited)?;
+            }
+        }
+        result
+    }
 
-                let conditions: Vec<_> = conditions
-                    .values()
-                    .map(|v| v as &dyn rusqlite::ToSql)
-                    .collect();
+    fn select(
+        &mut self,
+        table: &str,
+        columns: Vec<String>,
+        conditions: ColumnSet,
+    ) -> Result<Vec<ColumnSet>, DobbyError> {
+        self.execute(Query::Select { from: table.to_string(), columns, conditions })
+    }
 
-                let mut rows: Vec<ColumnSet> = stmt
-                    .query_map(&conditions[..], |row| {
-                        let mut result = HashMap::new();
-                        for column in columns.iter() {
-                            let (name, data_type, index) = column;
-                            let value = match data_type {
-                                DataType::Int => TypedValue::Int(row.get_unwrap(*index)),
-                                DataType::String => TypedValue::String(row.get_unwrap(*index)),
-                                DataType::Float => TypedValue::Float(row.get_unwrap(*index)),
-                                _ => unreachable!(),
-                            };
-
-                            result.insert(name.clone(), value);
-                        }
-                        Ok(result)
-                    })?
-                    .collect::<Result<_, _>>()?;
+    /// Values of the key column in the rows that match the conditions
+    fn keys(
+        &mut self,
+        table: &str,
+        key: &str,
+        conditions: ColumnSet,
+    ) -> Result<Vec<TypedValue>, DobbyError> {
+        Ok(self
+            .select(table, vec![key.to_string()], conditions)?
+            .into_iter()
+            .map(|mut row| row.remove(key).unwrap())
+            .collect())
+    }
 
-                for row in rows.iter_mut() {
-                    for (column, data_type) in self.schema.tables[from].iter() {
-                        if row.contains_key(column) {
-                            let value = row[column].clone();
-                            row.insert(column.clone(), value.coerce(*data_type)?);
-                        }
+    /// Finds the rows referencing the ones with the keys, which restrict their deletion,
+    /// following the references that cascade. `visited` holds the rows known to be deleted.
+    fn check_delete(
+        &mut self,
+        table: &str,
+        keys: Vec<TypedValue>,
+        restrict: bool,
+        visited: &mut HashSet<(String, String)>,
+    ) -> Result<(), DobbyError> {
+        for (child, column, reference) in self.schema.references_to(table) {
+            let child_key = self.schema.auto_increment(&child).map(|(key, _)| key);
+            for key in &keys {
+                let conditions: ColumnSet = [(column.clone(), key.clone())].into();
+                if restrict || reference.on_delete == OnDelete::Restrict {
+                    if !self.select(&child, vec![], conditions)?.is_empty() {
+                        return Err(DobbyError::Referenced(table.to_string(), child));
                     }
+                } else if let Some(child_key) = &child_key {
+                    let keys = self
+                        .keys(&child, child_key, conditions)?
+                        .into_iter()
+                        .filter(|key| visited.insert((child.clone(), key.to_string())))
+                        .collect();
+                    self.check_delete(&child, keys, false, visited)?;
                 }
-                // TODO add a RETURNING clause to delete, insert, update
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stream(
+        &mut self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        if !matches!(query, Query::Select { .. }) {
+            for row in self.execute(query)? {
+                if !sink(row) {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        self.select_rows(query, None, &mut |_, row| sink(row))
+    }
+
+    /// Reads up to `limit` selected rows past the `cursor`, the rowid of the last row read
+    pub fn page(
+        &mut self,
+        query: Query,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Result<Page, DobbyError> {
+        if !matches!(query, Query::Select { .. }) {
+            return Ok(Page { rows: self.execute(query)?, next: None });
+        }
+        // rowids can be negative, and the cursor keeps their bits
+        let mut last = cursor.map_or(i64::MIN, |cursor| cursor as i64);
+        let mut rows = Vec::new();
+        self.select_rows(query, Some((last, limit)), &mut |rowid, row| {
+            last = rowid;
+            rows.push(row);
+            true
+        })?;
+        let next = (rows.len() == limit).then_some(last as u64);
+        Ok(Page { rows, next })
+    }
+
+    /// Feeds the selected rows into `sink` along with their rowids, which are only read
+    /// for a page of up to a number of rows past a rowid
+    fn select_rows(
+        &mut self,
+        mut query: Query,
+        page: Option<(i64, usize)>,
+        sink: &mut dyn FnMut(i64, ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        self.coerce_query(&mut query)?;
+        let Query::Select { from, columns, conditions } = &query else {
+            unreachable!()
+        };
+        let columns: Vec<_> = self.schema.tables[from]
+            .iter()
+            .filter(|(column, _)| columns.is_empty() || columns.contains(column))
+            .cloned()
+            .collect();
+
+        let conditions = self.bound(from, conditions, true)?;
+        let mut conditions: Vec<_> = conditions
+            .iter()
+            .map(|v| v.as_ref() as &dyn rusqlite::ToSql)
+            .collect();
+        let sql = match &page {
+            Some((after, limit)) => {
+                conditions.push(after);
+                query.paged_sql(*limit)
+            }
+            None => query.to_sql(),
+        };
+        let mut stmt = self.db.prepare(&sql)?;
+
+        let mut rows = stmt.query(&conditions[..])?;
+        while let Some(row) = rows.next()? {
+            let rowid = if page.is_some() { row.get(0)? } else { 0 };
+            if !sink(rowid, read_row(row, &columns)?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute(&mut self, mut query: Query) -> Result<Vec<ColumnSet>, DobbyError> {
+        if let Query::Insert { into, values } = &mut query {
+            self.schema.fill_defaults(into, values);
+        }
+        self.coerce_query(&mut query)?;
+        match &query {
+            Query::Select { .. } => {
+                let mut rows = Vec::new();
+                self.stream(query, &mut |row| {
+                    rows.push(row);
+                    true
+                })?;
                 Ok(rows)
             }
             Query::Insert { values, into } => {
                 let mut stmt = self.db.prepare(&query.to_sql())?;
-                for (column, data_type) in self.schema.tables[into].iter() {
-                    let value = values[column].clone();
-                    value.coerce(*data_type)?.validate()?;
+                let auto_increment = self.schema.auto_increment(into).map(|(column, _)| column);
+                let mut row = HashMap::new();
+                for (column, _) in self.schema.tables[into].iter() {
+                    match values.get(column) {
+                        Some(value) => {
+                            row.insert(column.clone(), value.clone());
+                        }
+                        // SQLite generates it
+                        None if auto_increment.as_ref() == Some(column) => {}
+                        None => {
+                            return Err(DobbyError::IncompleteData(column.clone(), into.clone()))
+                        }
+                    }
                 }
-                let values: Vec<_> = values.values().map(|v| v as &dyn rusqlite::ToSql).collect();
-                stmt.execute(&values[..])?;
-                Ok(vec![])
+                self.schema.check_auto_increment(into, values)?;
+                self.schema.check_rules(into, &row)?;
+                let values = self.bound(into, values, false)?;
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|v| v.as_ref() as &dyn rusqlite::ToSql)
+                    .collect();
+                let inserted = stmt.execute(&values[..]).map_err(DobbyError::from);
+                drop(stmt);
+                let inserted = self.check_unique_key(&query, inserted);
+                self.check_foreign_keys(&query, inserted)?;
+
+                if let Some(column) = auto_increment {
+                    let id = self.db.last_insert_rowid();
+                    row.entry(column).or_insert(id.into());
+                }
+                self.schema.advance_auto_increment(into, &row);
+                Ok(vec![row])
             }
-            Query::Update { set, table, conditions } => {
-                let mut stmt = self.db.prepare(&query.to_sql())?;
-                for (column, data_type) in self.schema.tables[table].iter() {
-                    if set.contains_key(column) {
-                        let value = set[column].clone();
-                        value.coerce(*data_type)?.validate()?;
-                    }
+            // no row changes, as in the native engine, and `SET` can't be empty
+            Query::Update { set, .. } if set.is_empty() => Ok(vec![]),
+            Query::Update { set, table, conditions, .. } => {
+                let key = self.schema.auto_increment(table).map(|(key, _)| key);
+                let sets_key = key.is_some_and(|key| set.contains_key(&key));
+                if sets_key {
+                    self.schema.check_auto_increment(table, set)?;
                 }
-                let values: Vec<_> = set.values().map(|v| v as &dyn rusqlite::ToSql).collect();
+                self.schema.check_rules(table, set)?;
+                let values = self.bound(table, set, false)?;
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|v| v.as_ref() as &dyn rusqlite::ToSql)
+                    .collect();
+                let conditions = self.bound(table, conditions, true)?;
                 let conditions: Vec<_> = conditions
-                    .values()
-                    .map(|v| v as &dyn rusqlite::ToSql)
+                    .iter()
+                    .map(|v| v.as_ref() as &dyn rusqlite::ToSql)
                     .collect();
-                stmt.execute(&[values, conditions].concat()[..])?;
-                Ok(vec![])
+                let updated = returning(
+                    &self.db,
+                    &query.to_sql(),
+                    &[&values[..], &conditions, &values].concat(),
+                    &self.schema.tables[table],
+                );
+                let updated = self.check_unique_key(&query, updated);
+                let updated = self.check_foreign_keys(&query, updated)?;
+                if sets_key && !updated.is_empty() {
+                    self.schema.advance_auto_increment(table, set);
+                }
+                Ok(updated)
             }
-            Query::Delete { conditions, .. } => {
-                let mut stmt = self.db.prepare(&query.to_sql())?;
+            Query::Delete { from, conditions, .. } => {
+                let conditions = self.bound(from, conditions, true)?;
                 let conditions: Vec<_> = conditions
-                    .values()
-                    .map(|v| v as &dyn rusqlite::ToSql)
+                    .iter()
+                    .map(|v| v.as_ref() as &dyn rusqlite::ToSql)
                     .collect();
-                stmt.execute(&conditions[..])?;
-                Ok(vec![])
+                let deleted = returning(
+                    &self.db,
+                    &query.to_sql(),
+                    &conditions,
+                    &self.schema.tables[from],
+                );
+                self.check_foreign_keys(&query, deleted)
+            }
+            Query::ListDeleted { .. } | Query::Undelete { .. } => {
+                // SQLite deletes rows for real
+                Err(DobbyError::Unsupported("Undelete".into()))
             }
             Query::Drop { table } => {
-                let mut stmt = self.db.prepare(&query.to_sql())?;
-                self.schema.drop_table(table.clone())?;
+                if !self.schema.tables.contains_key(table) {
+                    return Err(DobbyError::TableNotFound(table.clone()));
+                }
+                self.purge_expired()?;
 
-                stmt.execute([])?;
+                let mut schema = self.schema.clone();
+                schema.trash_table(table.clone(), now())?;
+                self.db.execute(
+                    &format!("ALTER TABLE {} RENAME TO {}", quoted(table), trashed(table)),
+                    [],
+                )?;
+                self.move_table(table, &trash_name(table));
+                self.schema = schema;
                 Ok(vec![])
             }
-            Query::Create { table, columns } => {
-                let mut stmt = self.db.prepare(&query.to_sql())?;
-                self.schema.create_table(table.clone(), columns.clone())?;
-                stmt.execute([])?;
+            Query::Undrop { table } => {
+                self.purge_expired()?;
+                let mut schema = self.schema.clone();
+                schema.restore_table(table.clone())?;
+                self.db.execute(
+                    &format!("ALTER TABLE {} RENAME TO {}", trashed(table), quoted(table)),
+                    [],
+                )?;
+                self.move_table(&trash_name(table), table);
+                self.schema = schema;
+                Ok(vec![])
+            }
+            Query::ListTrash => {
+                self.purge_expired()?;
+                let mut trash: Vec<_> = self.schema.trash.iter().collect();
+                trash.sort_by_key(|(table, _)| table.as_str());
+                Ok(trash
+                    .into_iter()
+                    .map(|(table, dropped)| trash_row(table, dropped, self.retention))
+                    .collect())
+            }
+            Query::Purge { table } => {
+                let tables = match table {
+                    Some(table) => vec![table.clone()],
+                    None => self.schema.trash.keys().cloned().collect(),
+                };
+                let purged = tables
+                    .into_iter()
+                    .map(|table| self.purge(table))
+                    .collect::<Result<_, _>>()?;
+                self.db.execute("VACUUM", [])?;
+                Ok(purged)
+            }
+            Query::Rename { table, to } => {
+                let mut schema = self.schema.clone();
+                schema.rename_table(table.clone(), to.clone())?;
+                self.db.execute(&query.to_sql(), [])?;
+                self.move_table(table, to);
+                self.schema = schema;
                 Ok(vec![])
             }
-            Query::Alter { table, rename } => {
-                self.schema.alter_table(table.clone(), rename.clone())?;
-                let tx = self.db.transaction()?;
-                for (old, new) in rename {
-                    let mut stmt = tx.prepare(&format!(
-                        "ALTER TABLE {} RENAME COLUMN {} TO {}",
-                        table, old, new
-                    ))?;
-                    stmt.execute([])?;
+            Query::Create {
+                table,
+                columns,
+                auto_increment,
+                defaults,
+                rules,
+                references,
+                labels,
+                precisions,
+                generated,
+            } => {
+                let mut schema = self.schema.clone();
+                schema.create_table(table.clone(), columns.clone())?;
+                if let Some(column) = auto_increment {
+                    schema.set_auto_increment(table, column)?;
+                }
+                schema.set_labels(table, labels.clone())?;
+                schema.set_precisions(table, precisions.clone())?;
+                schema.set_generated(table, generated.clone())?;
+                schema.set_rules(table, rules.clone())?;
+                schema.set_references(table, references.clone())?;
+                schema.set_defaults(table, defaults.clone())?;
+                let definitions =
+                    column_definitions(&schema.tables[table], schema.attributes.get(table));
+                self.db.execute(
+                    &format!("CREATE TABLE {} ({})", quoted(table), definitions),
+                    [],
+                )?;
+                self.schema = schema;
+                Ok(vec![])
+            }
+            Query::Alter {
+                table,
+                rename,
+                add,
+                drop,
+                retype,
+                defaults,
+                labels,
+                precisions,
+                generated,
+            } => {
+                let mut schema = self.schema.clone();
+                schema.drop_columns(table.clone(), drop.clone())?;
+                schema.retype_columns(table.clone(), retype.clone())?;
+                schema.alter_table(table.clone(), rename.clone())?;
+                schema.add_columns(
+                    table.clone(),
+                    add.clone(),
+                    defaults.clone(),
+                    labels.clone(),
+                    precisions.clone(),
+                    generated.clone(),
+                )?;
+                let has_rows: bool = self.db.query_row(
+                    &format!("SELECT EXISTS (SELECT 1 FROM {})", quoted(table)),
+                    [],
+                    |row| row.get(0),
+                )?;
+                if has_rows {
+                    schema.check_added_columns(table, add)?;
+                }
+
+                if !retype.is_empty() {
+                    let mut failed = Vec::new();
+                    let select = Query::Select {
+                        from: table.clone(),
+                        columns: vec![],
+                        conditions: HashMap::new(),
+                    };
+                    self.stream(select, &mut |row| {
+                        if convert(&row, retype).is_err() {
+                            failed.push(row);
+                        }
+                        true
+                    })?;
+                    if !failed.is_empty() {
+                        return Err(DobbyError::ConversionFailed(failed));
+                    }
                 }
-                tx.commit()?;
 
+                let rebuild = !add.is_empty() || !drop.is_empty() || !retype.is_empty();
+                // the table would be recreated from the schema, losing its columns left out
+                // of it, and its indexes, triggers and constraints dobby doesn't know of
+                if rebuild && self.path.is_none() {
+                    return Err(DobbyError::Unsupported(format!(
+                        "Adding, dropping or retyping columns of table {} of an existing SQLite file",
+                        table
+                    )));
+                }
+                let columns = &self.schema.tables[table];
+                // SQLite would delete the rows referencing the old table when it's dropped
+                self.db.execute("PRAGMA foreign_keys = OFF", [])?;
+                let altered = self
+                    .db
+                    .transaction()
+                    .map_err(DobbyError::from)
+                    .and_then(|tx| {
+                        if rebuild {
+                            rebuild_table(&tx, table, columns, &schema, |row| {
+                                let mut row: ColumnSet = convert(&row, retype)?
+                                    .into_iter()
+                                    .filter(|(column, _)| !drop.contains(column))
+                                    .map(|(column, value)| match rename.get(&column) {
+                                        Some(renamed) => (renamed.clone(), value),
+                                        None => (column, value),
+                                    })
+                                    .collect();
+                                schema.fill_defaults(table, &mut row);
+                                Ok(row)
+                            })?;
+                        } else {
+                            for (old, new) in rename {
+                                tx.execute(
+                                    &format!(
+                                        "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                                        quoted(table),
+                                        quoted(old),
+                                        quoted(new)
+                                    ),
+                                    [],
+                                )?;
+                            }
+                        }
+                        Ok(tx.commit()?)
+                    });
+                enable_foreign_keys(&self.db);
+                altered?;
+
+                // rebuilt tables store timestamps like dobby does
+                if rebuild {
+                    self.timestamp_formats.remove(table);
+                } else if let Some(formats) = self.timestamp_formats.get_mut(table) {
+                    let renamed: Vec<_> = rename
+                        .iter()
+                        .filter_map(|(old, new)| Some((new.clone(), formats.remove(old)?)))
+                        .collect();
+                    formats.extend(renamed);
+                }
+                self.schema = schema;
                 Ok(vec![])
             }
         }
     }
 }
 
+/// Rebuilds the table with its columns in the new schema, copying the rows through
+/// `convert`, since SQLite can't change the type or the constraints of a column
+fn rebuild_table<F>(
+    db: &Connection,
+    table: &str,
+    columns: &[(String, DataType)],
+    schema: &Schema,
+    mut convert: F,
+) -> Result<(), DobbyError>
+where
+    F: FnMut(ColumnSet) -> Result<ColumnSet, DobbyError>,
+{
+    let rebuilt = format!("{}.tmp", table);
+    let new_columns = &schema.tables[table];
+    db.execute(
+        &format!(
+            "CREATE TABLE {} ({})",
+            quoted(&rebuilt),
+            column_definitions(new_columns, schema.attributes.get(table))
+        ),
+        [],
+    )?;
+    {
+        let mut select = db.prepare(&format!("SELECT * FROM {}", quoted(table)))?;
+        let mut insert = db.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quoted(&rebuilt),
+            new_columns
+                .iter()
+                .map(|(column, _)| quoted(column))
+                .collect::<Vec<_>>()
+                .join(", "),
+            new_columns
+                .iter()
+                .map(|_| "?")
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let row = convert(read_row(row, columns)?)?;
+            let values = new_columns
+                .iter()
+                .map(|(column, _)| match row.get(column) {
+                    Some(value) => Ok(value as &dyn rusqlite::ToSql),
+                    None => Err(DobbyError::IncompleteData(
+                        column.clone(),
+                        table.to_string(),
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            insert.execute(&values[..])?;
+        }
+    }
+    // the counter of the auto-increment column goes on from where it was
+    if schema.auto_increment(table).is_some() {
+        db.execute("DELETE FROM sqlite_sequence WHERE name = ?", [&rebuilt])?;
+        db.execute(
+            "INSERT INTO sqlite_sequence (name, seq) SELECT ?, seq FROM sqlite_sequence WHERE name = ?",
+            [&rebuilt, table],
+        )?;
+    }
+    db.execute(&format!("DROP TABLE {}", quoted(table)), [])?;
+    db.execute(
+        &format!(
+            "ALTER TABLE {} RENAME TO {}",
+            quoted(&rebuilt),
+            quoted(table)
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Executes a statement with a RETURNING clause, reading the rows it returns
+fn returning(
+    db: &Connection,
+    sql: &str,
+    params: &[&dyn rusqlite::ToSql],
+    columns: &[(String, DataType)],
+) -> Result<Vec<ColumnSet>, DobbyError> {
+    let mut stmt = db.prepare(sql)?;
+    let mut rows = stmt.query(params)?;
+    let mut result = Vec::new();
+    while let Some(row) = rows.next()? {
+        result.push(read_row(row, columns)?);
+    }
+    Ok(result)
+}
+
+/// Reads the columns of a row, converting the stored values to the datatypes of the columns.
+/// SQLite files that weren't created by dobby can store values of any class in a column,
+/// so it's taken from the value itself
+fn read_row(row: &rusqlite::Row, columns: &[(String, DataType)]) -> Result<ColumnSet, DobbyError> {
+    let mut result = HashMap::new();
+    for (column, data_type) in columns {
+        let value = match row.get_ref(column.as_str())? {
+            ValueRef::Integer(i) => TypedValue::Int(i),
+            ValueRef::Real(f) => TypedValue::Float(f),
+            // JSON documents are stored as JSON text
+            ValueRef::Text(t) if *data_type == DataType::Json => match serde_json::from_slice(t) {
+                Ok(json) => TypedValue::Json(json),
+                Err(_) => {
+                    let text = String::from_utf8_lossy(t).into_owned();
+                    return Err(DobbyError::InvalidValue(
+                        TypedValue::String(text),
+                        DataType::Json,
+                    ));
+                }
+            },
+            ValueRef::Text(_) => TypedValue::String(row.get(column.as_str())?),
+            ValueRef::Blob(b) => TypedValue::Bytes(b.to_vec()),
+            // nullable columns of SQLite files aren't in the schema, but the file can change
+            ValueRef::Null => {
+                return Err(DobbyError::Unsupported(format!(
+                    "NULL in column {}",
+                    column
+                )))
+            }
+        };
+        result.insert(column.clone(), value.coerce(*data_type)?);
+    }
+    Ok(result)
+}
+
+/// Builds the schema of an SQLite file from its tables, and its trash from the tables dobby
+/// dropped, along with the columns left out of it and the formats of the timestamp columns
+/// that aren't dobby's, by the names of the tables in the file
+fn read_schema(
+    db: &Connection,
+    name: String,
+) -> Result<(Schema, Unsupported, TimestampFormats), DobbyError> {
+    let mut schema = Schema::new_sqlite(name);
+    let mut unsupported = HashMap::new();
+    let mut timestamp_formats = HashMap::new();
+    let mut tables: Vec<String> = db
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    // dropped tables are moved to the trash first, in case a table of the same name was created since
+    tables.sort_by_key(|table| !table.starts_with('~'));
+    for sqlite_name in tables {
+        // tables dobby dropped are kept in the file under a `~` prefix
+        let (table, dropped) = match sqlite_name.strip_prefix('~') {
+            Some(table) => (table.to_string(), true),
+            None => (sqlite_name.clone(), false),
+        };
+        if let Err(err) = Schema::validate_name(&table) {
+            log::warn!("Table {} is left out: {}", sqlite_name, err);
+            continue;
+        }
+        // selects are read a page at a time, by rowid
+        let without_rowid: bool = db.query_row(
+            "SELECT wr FROM pragma_table_list WHERE schema = 'main' AND name = ?",
+            [&sqlite_name],
+            |row| row.get(0),
+        )?;
+        if without_rowid {
+            log::warn!("Table {} is left out: it has no rowid", sqlite_name);
+            continue;
+        }
+        // name, declared type, NOT NULL, DEFAULT and whether it's in the primary key
+        let info: Vec<(String, String, bool, Option<String>, bool)> = db
+            .prepare(&format!("PRAGMA table_info({})", quoted(&sqlite_name)))?
+            .query_map([], |row| {
+                Ok((
+                    row.get("name")?,
+                    row.get("type")?,
+                    row.get("notnull")?,
+                    row.get("dflt_value")?,
+                    row.get::<_, i64>("pk")? > 0,
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        // the alias of the rowid, which SQLite generates
+        let rowid = match &info.iter().filter(|column| column.4).collect::<Vec<_>>()[..] {
+            [(column, declared, ..)] if declared.eq_ignore_ascii_case("INTEGER") => {
+                Some(column.clone())
+            }
+            _ => None,
+        };
+
+        let mut columns = Vec::new();
+        let mut defaults = HashMap::new();
+        let mut left_out = HashMap::new();
+        let mut formats = HashMap::new();
+        for (column, declared, not_null, default, _) in info {
+            let reason = match declared_data_type(&declared) {
+                None => format!("unknown type {:?}", declared),
+                Some(_) if RESERVED_COLUMNS.contains(&column.as_str()) => {
+                    "reserved name, taken by a flag of REST query strings".into()
+                }
+                Some(_) if Schema::validate_column_name(&column).is_err() => "invalid name".into(),
+                // dobby has no value for NULL
+                Some(_) if !not_null && rowid.as_ref() != Some(&column) => "nullable".into(),
+                Some(data_type) => {
+                    if data_type == DataType::Timestamp {
+                        match timestamp_format(db, &sqlite_name, &column)? {
+                            Ok(TIMESTAMP_FORMAT) => {}
+                            Ok(format) => {
+                                formats.insert(column.clone(), format);
+                            }
+                            Err(reason) => {
+                                left_out.insert(column, reason);
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(default) = default.as_deref().and_then(default_literal) {
+                        defaults.insert(column.clone(), default);
+                    }
+                    columns.push((column, data_type));
+                    continue;
+                }
+            };
+            left_out.insert(column, reason);
+        }
+        if columns.is_empty() {
+            log::warn!(
+                "Table {} is left out: none of its columns are supported",
+                sqlite_name
+            );
+            continue;
+        }
+        schema.create_table(table.clone(), columns)?;
+        if let Some(column) = rowid {
+            schema.set_auto_increment(&table, &column)?;
+        }
+        // defaults that don't fit the column are left to SQLite
+        for (column, default) in defaults {
+            let _ = schema.set_defaults(&table, [(column, default)].into());
+        }
+        if !formats.is_empty() {
+            timestamp_formats.insert(sqlite_name.clone(), formats);
+        }
+        if !left_out.is_empty() {
+            unsupported.insert(sqlite_name, left_out);
+        }
+        // the time of the drop isn't stored, so the retention starts over
+        if dropped {
+            schema.trash_table(table, now())?;
+        }
+    }
+    Ok((schema, unsupported, timestamp_formats))
+}
+
+/// The text format the timestamps of a column are stored in, or why dobby can't serve them,
+/// e.g. unix times in an `INTEGER`. Every value has to be in the same format
+fn timestamp_format(
+    db: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<Result<&'static str, String>, DobbyError> {
+    let mut formats = vec![TIMESTAMP_FORMAT];
+    formats.extend(SQLITE_TIMESTAMP_FORMATS);
+    let mut stmt = db.prepare(&format!(
+        "SELECT typeof({0}), {0} FROM {1}",
+        quoted(column),
+        quoted(table)
+    ))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let class: String = row.get(0)?;
+        if class != "text" {
+            return Ok(Err(format!("timestamps stored as {}", class)));
+        }
+        let text: String = row.get(1)?;
+        formats.retain(|format| {
+            NaiveDateTime::parse_from_str(&text, format)
+                .is_ok_and(|t| t.format(format).to_string() == text)
+        });
+        if formats.is_empty() {
+            return Ok(Err(
+                "timestamps in an unknown format, or in more than one".into()
+            ));
+        }
+    }
+    Ok(Ok(formats[0]))
+}
+
+/// The datatype of a column declared in an SQLite file that wasn't created by dobby:
+/// the names of dobby's datatypes first, then SQLite's rules of type affinity
+fn declared_data_type(declared: &str) -> Option<DataType> {
+    let declared = declared.to_uppercase();
+    let name = declared.split('(').next().unwrap_or_default().trim();
+    let contains = |parts: &[&str]| parts.iter().any(|part| declared.contains(part));
+    match name {
+        "BOOL" | "BOOLEAN" => Some(DataType::Bool),
+        "DATE" => Some(DataType::Date),
+        "DATETIME" | "TIMESTAMP" => Some(DataType::Timestamp),
+        "DECIMAL" | "NUMERIC" => Some(DataType::Decimal),
+        "JSON" => Some(DataType::Json),
+        "UUID" => Some(DataType::Uuid),
+        _ if contains(&["INT"]) => Some(DataType::Int),
+        _ if contains(&["CHAR", "CLOB", "TEXT"]) => Some(DataType::String),
+        _ if contains(&["BLOB"]) => Some(DataType::Bytes),
+        _ if contains(&["REAL", "FLOA", "DOUB"]) => Some(DataType::Float),
+        _ => None,
+    }
+}
+
+/// The value of a DEFAULT clause, if it's a literal rather than an expression
+fn default_literal(default: &str) -> Option<TypedValue> {
+    if let Some(text) = default
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        return Some(TypedValue::String(text.replace("''", "'")));
+    }
+    default
+        .parse::<i64>()
+        .map(TypedValue::Int)
+        .or_else(|_| default.parse::<f64>().map(TypedValue::Float))
+        .ok()
+}
+
+/// SQLite only enforces the REFERENCES clauses when asked to, per connection
+fn enable_foreign_keys(db: &Connection) {
+    db.execute("PRAGMA foreign_keys = ON", [])
+        .expect("Failed to enable foreign keys");
+}
+
+/// Quotes a table or column name, so that it can't be taken for a keyword or end the statement
+fn quoted(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Name of the SQLite table that holds the dropped table's data
+fn trash_name(table: &str) -> String {
+    format!("~{}", table)
+}
+
+/// Quoted name of the SQLite table that holds the dropped table's data
+fn trashed(table: &str) -> String {
+    quoted(&trash_name(table))
+}
+
+fn column_definitions(
+    columns: &[(String, DataType)],
+    attributes: Option<&HashMap<String, Attributes>>,
+) -> String {
+    columns
+        .iter()
+        .map(|(name, data_type)| {
+            let column = quoted(name);
+            let attributes = attributes
+                .and_then(|attributes| attributes.get(name))
+                .cloned()
+                .unwrap_or_default();
+            let mut definition = if attributes.auto_increment.is_some() {
+                format!("{} INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL", column)
+            } else {
+                format!("{} {} NOT NULL", column, data_type.to_sql())
+            };
+            if *data_type == DataType::Json {
+                definition += &format!(" CHECK (json_valid({}))", column);
+            }
+            if !attributes.labels.is_empty() {
+                let labels = attributes
+                    .labels
+                    .iter()
+                    .map(|label| sql_literal(&TypedValue::Enum(label.clone())));
+                definition += &format!(
+                    " CHECK ({} IN ({}))",
+                    column,
+                    labels.collect::<Vec<_>>().join(", ")
+                );
+            }
+            if let Some(default) = attributes.default {
+                definition += &format!(" DEFAULT {}", sql_literal(&default));
+            }
+            if let Some(reference) = attributes.references {
+                let on_delete = match reference.on_delete {
+                    OnDelete::Restrict => "RESTRICT",
+                    OnDelete::Cascade => "CASCADE",
+                };
+                definition += &format!(
+                    " REFERENCES {}({}) ON DELETE {}",
+                    quoted(&reference.table),
+                    quoted(&reference.column),
+                    on_delete
+                );
+            }
+            definition
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Converts a path checked by `json_path_segments` to the JSON1 syntax, e.g. `$.items[0].name`
+fn sqlite_json_path(path: &str) -> String {
+    path.split('.').fold("$".to_string(), |path, segment| {
+        if segment.chars().all(|c| c.is_ascii_digit()) {
+            format!("{}[{}]", path, segment)
+        } else {
+            format!("{}.{}", path, segment)
+        }
+    })
+}
+
+/// Formats a value as an SQL literal, for the places where
+/// parameters can't be bound (e.g. DEFAULT clauses)
+fn sql_literal(value: &TypedValue) -> String {
+    match value {
+        TypedValue::Int(i) => i.to_string(),
+        TypedValue::Float(f) => format!("{:?}", f),
+        TypedValue::Bool(b) => (*b as i64).to_string(),
+        TypedValue::Timestamp(t) => format!("'{}'", t.format(TIMESTAMP_FORMAT)),
+        TypedValue::Bytes(b) => format!(
+            "X'{}'",
+            b.iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<String>()
+        ),
+        TypedValue::TimestampInvl(t1, t2) => format!(
+            "'{}..{}'",
+            t1.format(TIMESTAMP_FORMAT),
+            t2.format(TIMESTAMP_FORMAT)
+        ),
+        value => format!("'{}'", value.to_string().replace('\'', "''")),
+    }
+}
+
 impl Drop for Sqlite {
     fn drop(&mut self) {
-        self.schema.dump(&self.path).expect("Failed to dump schema");
+        if let Some(path) = &self.path {
+            self.schema.dump(path).expect("Failed to dump schema");
+        }
     }
 }
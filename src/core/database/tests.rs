@@ -0,0 +1,1727 @@
+use super::*;
+use crate::core::types::{
+    CoercionRule, OnDelete, Precision, Reference, Rule, TypedValue, UuidVersion,
+};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Creates a database at the path
+type Constructor = fn(PathBuf) -> Arc<dyn Database>;
+
+/// Every backend, by name
+const BACKENDS: &[(&str, Constructor)] = &[
+    ("native", |path| {
+        Arc::new(Mutex::new(dobby::Dobby::create(path, "test".into())))
+    }),
+    #[cfg(feature = "sqlite")]
+    ("sqlite", |path| {
+        Arc::new(Mutex::new(sqlite::Sqlite::create(path, "test".into())))
+    }),
+];
+
+/// Every backend, each with the table test(id int, price float, name string)
+fn databases(dir: &tempfile::TempDir) -> Vec<Arc<dyn Database>> {
+    BACKENDS
+        .iter()
+        .map(|(name, create)| {
+            let db = create(dir.path().join(name));
+            create_test_table(&db);
+            db
+        })
+        .collect()
+}
+
+fn create_test_table(db: &Arc<dyn Database>) {
+    db.execute(create(
+        "test",
+        &[
+            ("id", DataType::Int),
+            ("price", DataType::Float),
+            ("name", DataType::String),
+        ],
+    ))
+    .unwrap();
+}
+
+/// Creates a table without any column attributes
+fn create(table: &str, columns: &[(&str, DataType)]) -> Query {
+    Query::Create {
+        table: table.into(),
+        columns: columns
+            .iter()
+            .map(|(column, data_type)| (column.to_string(), *data_type))
+            .collect(),
+        auto_increment: None,
+        defaults: HashMap::new(),
+        rules: HashMap::new(),
+        references: HashMap::new(),
+        labels: HashMap::new(),
+        precisions: HashMap::new(),
+        generated: HashMap::new(),
+    }
+}
+
+fn insert(db: &Arc<dyn Database>, coercion: Coercion, values: ColumnSet) -> Result<(), DobbyError> {
+    let db = Coerced(Arc::clone(db), coercion, false);
+    db.execute(Query::Insert { into: "test".into(), values })
+        .map(|_| ())
+}
+
+fn row(id: TypedValue, price: TypedValue, name: TypedValue) -> ColumnSet {
+    [
+        ("id".into(), id),
+        ("price".into(), price),
+        ("name".into(), name),
+    ]
+    .into()
+}
+
+fn select(db: &Arc<dyn Database>, conditions: ColumnSet) -> Result<Vec<ColumnSet>, DobbyError> {
+    db.execute(Query::Select {
+        from: "test".into(),
+        columns: vec!["id".into()],
+        conditions,
+    })
+}
+
+#[test]
+fn coercion() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    for db in databases(&dir) {
+        coerce(&db)?;
+    }
+    Ok(())
+}
+
+fn coerce(db: &Arc<dyn Database>) -> Result<(), DobbyError> {
+    insert(db, Coercion::Strict, row(1.into(), 1.5.into(), "a".into()))?;
+    assert!(matches!(
+        insert(
+            db,
+            Coercion::Strict,
+            row("2".into(), 1.5.into(), "b".into())
+        ),
+        Err(DobbyError::CoercionNotAllowed(
+            _,
+            DataType::Int,
+            CoercionRule::ParseNumber,
+            _
+        ))
+    ));
+    assert!(matches!(
+        insert(db, Coercion::Strict, row(2.into(), 2.into(), "b".into())),
+        Err(DobbyError::CoercionNotAllowed(
+            _,
+            DataType::Float,
+            CoercionRule::Widening,
+            _
+        ))
+    ));
+
+    insert(db, Coercion::Lenient, row("2".into(), 2.into(), "b".into()))?;
+    assert!(matches!(
+        insert(db, Coercion::Lenient, row(3.into(), 3.into(), 3.into())),
+        Err(DobbyError::CoercionNotAllowed(
+            _,
+            DataType::String,
+            CoercionRule::Format,
+            _
+        ))
+    ));
+
+    insert(db, Coercion::Extended, row(3.0.into(), 3.into(), 3.into()))?;
+    assert!(matches!(
+        insert(db, Coercion::Extended, row(3.5.into(), 3.into(), 3.into())),
+        Err(DobbyError::CoercionFailed(
+            _,
+            DataType::Int,
+            CoercionRule::Narrowing
+        ))
+    ));
+
+    // conditions are text in query strings, even in the strict mode
+    let select = Query::Select {
+        from: "test".into(),
+        columns: vec!["name".into()],
+        conditions: [("id".into(), "3".into())].into(),
+    };
+    let rows = Coerced(Arc::clone(db), Coercion::Strict, true).execute(select.clone())?;
+    assert_eq!(rows, vec![[("name".into(), "3".into())].into()]);
+    // but typed ones are held to it
+    assert!(matches!(
+        Coerced(Arc::clone(db), Coercion::Strict, false).execute(select),
+        Err(DobbyError::CoercionNotAllowed(
+            _,
+            DataType::Int,
+            CoercionRule::ParseNumber,
+            _
+        ))
+    ));
+    let delete = Query::Delete {
+        from: "test".into(),
+        conditions: [("price".into(), 3.into())].into(),
+        all: false,
+    };
+    assert!(matches!(
+        Coerced(Arc::clone(db), Coercion::Strict, false).execute(delete),
+        Err(DobbyError::CoercionNotAllowed(
+            _,
+            DataType::Float,
+            CoercionRule::Widening,
+            _
+        ))
+    ));
+    assert_eq!(db.execute(select_from("test", json!({})))?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn float_equality() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    for db in databases(&dir) {
+        let insert = |id: i64, price: TypedValue| {
+            db.execute(Query::Insert {
+                into: "test".into(),
+                values: row(id.into(), price, "".into()),
+            })
+        };
+        let ids = |price: TypedValue| -> Result<Vec<TypedValue>, DobbyError> {
+            Ok(select(&db, [("price".into(), price)].into())?
+                .into_iter()
+                .map(|mut row| row.remove("id").unwrap())
+                .collect())
+        };
+
+        insert(1, 0.1.into())?;
+        insert(2, (-0.0).into())?;
+        insert(3, "0.30000000000000004".into())?;
+        assert!(matches!(
+            insert(4, "NaN".into()),
+            Err(DobbyError::NonFiniteFloat(f)) if f.is_nan()
+        ));
+        assert!(matches!(
+            insert(4, "-inf".into()),
+            Err(DobbyError::NonFiniteFloat(f)) if f == f64::NEG_INFINITY
+        ));
+
+        // values parsed from text are the same as the ones from JSON
+        assert_eq!(ids("0.1".into())?, vec![1.into()]);
+        assert_eq!(
+            ids(serde_json::from_str("0.30000000000000004").unwrap())?,
+            vec![3.into()]
+        );
+        // equality is exact, as in IEEE 754
+        assert_eq!(ids((0.1 + 0.2).into())?, vec![3.into()]);
+        assert_eq!(ids(0.3.into())?, vec![]);
+        // zero has one sign
+        assert_eq!(ids(0.0.into())?, vec![2.into()]);
+        assert_eq!(ids("-0".into())?, vec![2.into()]);
+        let zero = db.execute(Query::Select {
+            from: "test".into(),
+            columns: vec!["price".into()],
+            conditions: [("id".into(), 2.into())].into(),
+        })?;
+        assert!(matches!(zero[0]["price"], TypedValue::Float(f) if f.is_sign_positive()));
+        assert!(matches!(
+            ids("nan".into()),
+            Err(DobbyError::NonFiniteFloat(_))
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn injection() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    let injection = "x\"; DROP TABLE test; --";
+    for db in databases(&dir) {
+        db.execute(Query::Insert {
+            into: "test".into(),
+            values: row(1.into(), 1.5.into(), "'); DROP TABLE test; --".into()),
+        })?;
+
+        let invalid_name = |result: Result<Vec<ColumnSet>, DobbyError>| {
+            matches!(result, Err(DobbyError::InvalidName(_)))
+        };
+        let column_not_found = |result: Result<Vec<ColumnSet>, DobbyError>| {
+            matches!(result, Err(DobbyError::ColumnNotFound(_, _)))
+        };
+        assert!(column_not_found(select(
+            &db,
+            [("1 = 1 OR id".into(), 1.into())].into()
+        )));
+        assert!(column_not_found(db.execute(Query::Select {
+            from: "test".into(),
+            columns: vec!["id FROM test; DROP TABLE test; --".into()],
+            conditions: HashMap::new(),
+        })));
+        assert!(column_not_found(select(
+            &db,
+            [("payload.a') OR 1 = 1 --".into(), 1.into())].into()
+        )));
+        assert!(matches!(
+            db.execute(Query::Delete {
+                from: injection.into(),
+                conditions: HashMap::new(),
+                all: true,
+            }),
+            Err(DobbyError::TableNotFound(_))
+        ));
+        assert!(invalid_name(
+            db.execute(create(injection, &[("id", DataType::Int)]))
+        ));
+        assert!(invalid_name(
+            db.execute(create("", &[("id", DataType::Int)]))
+        ));
+        assert!(invalid_name(
+            db.execute(create("evil", &[(injection, DataType::Int)]))
+        ));
+        assert!(invalid_name(db.execute(Query::Rename {
+            table: "test".into(),
+            to: injection.into(),
+        })));
+        assert!(invalid_name(db.execute(Query::Alter {
+            table: "test".into(),
+            rename: [("name".into(), injection.into())].into(),
+            add: vec![],
+            drop: vec![],
+            retype: HashMap::new(),
+            defaults: HashMap::new(),
+            labels: HashMap::new(),
+            precisions: HashMap::new(),
+            generated: HashMap::new(),
+        })));
+        assert!(invalid_name(db.execute(Query::Alter {
+            table: "test".into(),
+            rename: HashMap::new(),
+            add: vec![(injection.into(), DataType::Int)],
+            drop: vec![],
+            retype: HashMap::new(),
+            defaults: HashMap::new(),
+            labels: HashMap::new(),
+            precisions: HashMap::new(),
+            generated: HashMap::new(),
+        })));
+
+        // the table and its row are untouched
+        let rows = select(
+            &db,
+            [("name".into(), "'); DROP TABLE test; --".into())].into(),
+        )?;
+        assert_eq!(rows, vec![[("id".into(), 1.into())].into()]);
+    }
+    Ok(())
+}
+
+#[test]
+fn keywords_as_names() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    for db in databases(&dir) {
+        db.execute(create(
+            "order",
+            &[("select", DataType::Int), ("from", DataType::String)],
+        ))?;
+        db.execute(Query::Insert {
+            into: "order".into(),
+            values: [("select".into(), 1.into()), ("from".into(), "Dobby".into())].into(),
+        })?;
+        db.execute(Query::Alter {
+            table: "order".into(),
+            rename: [("from".into(), "where".into())].into(),
+            add: vec![("group".into(), DataType::String)],
+            drop: vec![],
+            retype: HashMap::new(),
+            defaults: [("group".into(), "it's".into())].into(),
+            labels: HashMap::new(),
+            precisions: HashMap::new(),
+            generated: HashMap::new(),
+        })?;
+        db.execute(Query::Rename { table: "order".into(), to: "table".into() })?;
+        let rows = db.execute(Query::Select {
+            from: "table".into(),
+            columns: vec!["where".into(), "group".into()],
+            conditions: [("select".into(), 1.into())].into(),
+        })?;
+        assert_eq!(
+            rows,
+            vec![[
+                ("where".into(), "Dobby".into()),
+                ("group".into(), "it's".into())
+            ]
+            .into()]
+        );
+        db.execute(Query::Drop { table: "table".into() })?;
+        db.execute(Query::Undrop { table: "table".into() })?;
+    }
+    Ok(())
+}
+
+#[test]
+fn auto_increment_limit() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    for (backend, create_db) in BACKENDS {
+        let db = create_db(dir.path().join(backend));
+        let mut users = create(
+            "users",
+            &[("id", DataType::Int), ("name", DataType::String)],
+        );
+        if let Query::Create { auto_increment, .. } = &mut users {
+            *auto_increment = Some("id".into());
+        }
+        db.execute(users)?;
+        let insert = |values: serde_json::Value| db.execute(insert_into("users", values));
+        let exhausted = |result| matches!(result, Err(DobbyError::AutoIncrementExhausted(_, _)));
+
+        assert!(exhausted(insert(json!({"id": i64::MAX, "name": "a"}))));
+        insert(json!({"id": i64::MAX - 1, "name": "b"}))?;
+        assert!(exhausted(insert(json!({"name": "c"}))));
+        // the database is still usable
+        assert_eq!(db.execute(select_from("users", json!({})))?.len(), 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn json_text() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    for (backend, create_db) in BACKENDS {
+        let db = create_db(dir.path().join(backend));
+        db.execute(create(
+            "docs",
+            &[("id", DataType::Int), ("doc", DataType::Json)],
+        ))?;
+        db.execute(insert_into("docs", json!({"id": 1, "doc": "5"})))?;
+        db.execute(insert_into("docs", json!({"id": 2, "doc": "Dobby"})))?;
+        db.execute(insert_into("docs", json!({"id": 3, "doc": 5})))?;
+        db.execute(insert_into("docs", json!({"id": 4, "doc": {"name": "5"}})))?;
+        let ids = |conditions| -> Result<Vec<TypedValue>, DobbyError> {
+            let rows = db.execute(select_from("docs", conditions))?;
+            Ok(rows.into_iter().map(|row| row["id"].clone()).collect())
+        };
+
+        // text is kept as a JSON string, both when stored and when compared
+        let rows = db.execute(select_from("docs", json!({"id": 1})))?;
+        assert_eq!(rows[0]["doc"], TypedValue::Json(json!("5")));
+        assert_eq!(ids(json!({"doc": "5"}))?, vec![TypedValue::Int(1)]);
+        assert_eq!(ids(json!({"doc": "Dobby"}))?, vec![TypedValue::Int(2)]);
+        assert_eq!(ids(json!({"doc": 5}))?, vec![TypedValue::Int(3)]);
+        assert_eq!(ids(json!({"doc.name": "5"}))?, vec![TypedValue::Int(4)]);
+        assert_eq!(ids(json!({"doc.name": 5}))?, vec![]);
+    }
+    Ok(())
+}
+
+#[test]
+fn failed_drop() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Arc<dyn Database> = Arc::new(Mutex::new(dobby::Dobby::create(
+        dir.path().join("db"),
+        "test".into(),
+    )));
+    create_test_table(&db);
+    insert(
+        &db,
+        Coercion::Lenient,
+        row(1.into(), 1.5.into(), "a".into()),
+    )?;
+
+    // the trash directory can't be created, so the table file can't be moved
+    std::fs::write(dir.path().join("db").join(".trash"), "")?;
+    assert!(matches!(
+        db.execute(Query::Drop { table: "test".into() }),
+        Err(DobbyError::IoError(_))
+    ));
+    assert!(db.schema().trash.is_empty());
+    assert_eq!(select(&db, HashMap::new())?.len(), 1);
+    Ok(())
+}
+
+/// Counts the rows read in pages
+struct Counted(Arc<dyn Database>, Arc<AtomicUsize>);
+
+impl Database for Counted {
+    fn execute(&self, query: Query) -> Result<Vec<ColumnSet>, DobbyError> {
+        self.0.execute(query)
+    }
+
+    fn stream(
+        &self,
+        query: Query,
+        sink: &mut dyn FnMut(ColumnSet) -> bool,
+    ) -> Result<(), DobbyError> {
+        self.0.stream(query, sink)
+    }
+
+    fn page(&self, query: Query, cursor: Option<u64>, limit: usize) -> Result<Page, DobbyError> {
+        let page = self.0.page(query, cursor, limit)?;
+        self.1.fetch_add(page.rows.len(), Ordering::SeqCst);
+        Ok(page)
+    }
+
+    fn schema(&self) -> Schema {
+        self.0.schema()
+    }
+
+    fn columns(&self, table: &str) -> Option<Vec<(String, DataType)>> {
+        self.0.columns(table)
+    }
+}
+
+#[test]
+fn failed_rename() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Arc<dyn Database> = Arc::new(Mutex::new(dobby::Dobby::create(
+        dir.path().join("db"),
+        "test".into(),
+    )));
+    create_test_table(&db);
+    insert(
+        &db,
+        Coercion::Lenient,
+        row(1.into(), 1.5.into(), "a".into()),
+    )?;
+
+    // a directory is in the way, so the table file can't be renamed
+    std::fs::create_dir_all(dir.path().join("db").join("other").join("file"))?;
+    assert!(matches!(
+        db.execute(Query::Rename { table: "test".into(), to: "other".into() }),
+        Err(DobbyError::IoError(_))
+    ));
+    assert!(db.schema().tables.contains_key("test"));
+    assert!(!db.schema().tables.contains_key("other"));
+    assert_eq!(select(&db, HashMap::new())?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn undelete_checks() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Arc<dyn Database> = Arc::new(Mutex::new(dobby::Dobby::create(
+        dir.path().join("db"),
+        "test".into(),
+    )));
+    for (table, columns, references) in [
+        (
+            "users",
+            [("id", DataType::Int), ("name", DataType::String)],
+            HashMap::new(),
+        ),
+        (
+            "pets",
+            [("id", DataType::Int), ("owner", DataType::Int)],
+            [(
+                "owner".to_string(),
+                Reference {
+                    table: "users".into(),
+                    column: "id".into(),
+                    on_delete: OnDelete::Cascade,
+                },
+            )]
+            .into(),
+        ),
+    ] {
+        let mut query = create(table, &columns);
+        if let Query::Create { auto_increment, references: refs, .. } = &mut query {
+            *auto_increment = Some("id".into());
+            *refs = references;
+        }
+        db.execute(query)?;
+    }
+    db.execute(insert_into("users", json!({"name": "Harry"})))?;
+    db.execute(insert_into("users", json!({"name": "Ron"})))?;
+    db.execute(insert_into("pets", json!({"owner": 1})))?;
+    let undelete = |from: &str, conditions: serde_json::Value| Query::Undelete {
+        from: from.into(),
+        conditions: values(conditions),
+        all: true,
+    };
+    let delete = |conditions: serde_json::Value| Query::Delete {
+        from: "users".into(),
+        conditions: values(conditions),
+        all: false,
+    };
+
+    // the owner of the pet is deleted along with it
+    db.execute(delete(json!({"name": "Harry"})))?;
+    assert!(matches!(
+        db.execute(undelete("pets", json!({}))),
+        Err(DobbyError::MissingReference(..))
+    ));
+    db.execute(undelete("users", json!({"name": "Harry"})))?;
+    assert_eq!(db.execute(undelete("pets", json!({})))?.len(), 1);
+
+    // the key of the deleted row is taken by another one since
+    db.execute(delete(json!({"name": "Ron"})))?;
+    db.execute(insert_into("users", json!({"id": 2, "name": "Ginny"})))?;
+    assert!(matches!(
+        db.execute(undelete("users", json!({"name": "Ron"}))),
+        Err(DobbyError::DuplicateKey(..))
+    ));
+    // or by another restored one
+    db.execute(delete(json!({"name": "Ginny"})))?;
+    assert!(matches!(
+        db.execute(undelete("users", json!({}))),
+        Err(DobbyError::DuplicateKey(..))
+    ));
+    assert_eq!(
+        db.execute(Query::ListDeleted { from: "users".into(), conditions: HashMap::new() })?
+            .len(),
+        2
+    );
+
+    let safe: Arc<dyn Database> = Arc::new(SafeMode(Arc::clone(&db)));
+    let mut unfiltered = undelete("users", json!({}));
+    if let Query::Undelete { all, .. } = &mut unfiltered {
+        *all = false;
+    }
+    assert!(matches!(
+        safe.execute(unfiltered),
+        Err(DobbyError::UnfilteredQuery(_))
+    ));
+    assert_eq!(
+        safe.execute(undelete("users", json!({"name": "Ginny"})))?,
+        vec![values(json!({"id": 2, "name": "Ginny"}))]
+    );
+    Ok(())
+}
+
+#[test]
+fn retype_deleted() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Arc<dyn Database> = Arc::new(Mutex::new(dobby::Dobby::create(
+        dir.path().join("db"),
+        "test".into(),
+    )));
+    create_test_table(&db);
+    insert(
+        &db,
+        Coercion::Lenient,
+        row(1.into(), 1.5.into(), "1".into()),
+    )?;
+    insert(
+        &db,
+        Coercion::Lenient,
+        row(2.into(), 2.5.into(), "b".into()),
+    )?;
+    db.execute(Query::Delete {
+        from: "test".into(),
+        conditions: [("id".into(), 2.into())].into(),
+        all: false,
+    })?;
+
+    // the deleted row couldn't be restored if it was converted
+    let mut retype = alter_table("test");
+    if let Query::Alter { retype, .. } = &mut retype {
+        retype.insert("name".into(), DataType::Int);
+    }
+    assert!(matches!(
+        db.execute(retype),
+        Err(DobbyError::ConversionFailed(rows)) if rows.len() == 1
+    ));
+    let restored = db.execute(Query::Undelete {
+        from: "test".into(),
+        conditions: HashMap::new(),
+        all: true,
+    })?;
+    assert_eq!(restored, vec![row(2.into(), 2.5.into(), "b".into())]);
+    Ok(())
+}
+
+#[test]
+fn stalled_stream() -> Result<(), DobbyError> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _context = runtime.enter();
+    let dir = tempfile::tempdir().unwrap();
+    for db in databases(&dir) {
+        let rows = STREAM_BUFFER as i64 * 4;
+        for id in 0..rows {
+            insert(
+                &db,
+                Coercion::Lenient,
+                row(id.into(), 1.5.into(), "a".into()),
+            )?;
+        }
+
+        // nothing reads the stream, yet the database keeps serving queries
+        let read = Arc::new(AtomicUsize::new(0));
+        let mut receiver = stream_rows(
+            Arc::new(Counted(Arc::clone(&db), Arc::clone(&read))),
+            Query::Select {
+                from: "test".into(),
+                columns: vec![],
+                conditions: HashMap::new(),
+            },
+        );
+        std::thread::sleep(Duration::from_millis(100));
+        insert(
+            &db,
+            Coercion::Lenient,
+            row(rows.into(), 1.5.into(), "b".into()),
+        )?;
+        assert_eq!(select(&db, [("name".into(), "b".into())].into())?.len(), 1);
+        // and only reads the rows that fit in the channel, plus the page it waits to send
+        assert!(read.load(Ordering::SeqCst) <= 2 * STREAM_BUFFER);
+
+        let mut streamed = 0;
+        while let Some(row) = runtime.block_on(receiver.recv()) {
+            row?;
+            streamed += 1;
+        }
+        // the row inserted meanwhile is past the pages read so far
+        assert_eq!(streamed, rows + 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn altered_stream() -> Result<(), DobbyError> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _context = runtime.enter();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Arc<dyn Database> = Arc::new(Mutex::new(dobby::Dobby::create(
+        dir.path().join("db"),
+        "test".into(),
+    )));
+    create_test_table(&db);
+    let rows = STREAM_BUFFER as i64 * 4;
+    for id in 0..rows {
+        insert(
+            &db,
+            Coercion::Lenient,
+            row(id.into(), 1.5.into(), "a".into()),
+        )?;
+    }
+
+    let mut receiver = stream_rows(
+        Arc::clone(&db),
+        Query::Select {
+            from: "test".into(),
+            columns: vec![],
+            conditions: HashMap::new(),
+        },
+    );
+    std::thread::sleep(Duration::from_millis(100));
+    // the table file is rewritten in the new layout while the stream waits
+    let mut alter = alter_table("test");
+    if let Query::Alter { add, defaults, .. } = &mut alter {
+        add.push(("color".into(), DataType::String));
+        defaults.insert("color".into(), "red".into());
+    }
+    db.execute(alter)?;
+
+    let mut streamed = 0;
+    let mut last = None;
+    while let Some(row) = runtime.block_on(receiver.recv()) {
+        match row {
+            Ok(_) => streamed += 1,
+            Err(err) => last = Some(err),
+        }
+    }
+    // the pages read before are still sent, the rest isn't read from the new file
+    assert!(streamed < rows);
+    assert!(matches!(last, Some(DobbyError::TableChanged(_))));
+    assert_eq!(select(&db, HashMap::new())?.len(), rows as usize);
+    Ok(())
+}
+
+#[test]
+fn affected_rows() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    for db in databases(&dir) {
+        let by_id = |mut rows: Vec<ColumnSet>| {
+            rows.sort_by_key(|row| row["id"].to_string());
+            rows
+        };
+
+        let inserted = db.execute(Query::Insert {
+            into: "test".into(),
+            values: row("1".into(), 1.5.into(), "a".into()),
+        })?;
+        assert_eq!(inserted, vec![row(1.into(), 1.5.into(), "a".into())]);
+        for id in 2..=3 {
+            db.execute(Query::Insert {
+                into: "test".into(),
+                values: row(id.into(), 2.5.into(), "b".into()),
+            })?;
+        }
+
+        let update = |price: f64| {
+            db.execute(Query::Update {
+                table: "test".into(),
+                set: [("price".into(), price.into())].into(),
+                conditions: [("name".into(), "b".into())].into(),
+                all: false,
+            })
+        };
+        assert_eq!(
+            by_id(update(3.5)?),
+            vec![
+                row(2.into(), 3.5.into(), "b".into()),
+                row(3.into(), 3.5.into(), "b".into()),
+            ]
+        );
+        // rows that have the values already aren't updated
+        assert_eq!(update(3.5)?, vec![]);
+
+        let deleted = db.execute(Query::Delete {
+            from: "test".into(),
+            conditions: [("price".into(), 3.5.into())].into(),
+            all: false,
+        })?;
+        assert_eq!(
+            by_id(deleted),
+            vec![
+                row(2.into(), 3.5.into(), "b".into()),
+                row(3.into(), 3.5.into(), "b".into()),
+            ]
+        );
+        let deleted = db.execute(Query::Delete {
+            from: "test".into(),
+            conditions: HashMap::new(),
+            all: true,
+        })?;
+        assert_eq!(deleted, vec![row(1.into(), 1.5.into(), "a".into())]);
+    }
+    Ok(())
+}
+
+/// What a step of a scenario resulted in, comparable between backends
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// The rows as JSON, sorted, since the backends read them in different orders
+    Rows(Vec<String>),
+    /// The variant of the error, since the messages can quote backend-specific values
+    Error(String),
+}
+
+impl From<Result<Vec<ColumnSet>, DobbyError>> for Outcome {
+    fn from(result: Result<Vec<ColumnSet>, DobbyError>) -> Self {
+        match result {
+            Ok(rows) => {
+                let mut rows: Vec<_> = rows
+                    .into_iter()
+                    .map(|mut row| {
+                        // the trash's times depend on when the step ran
+                        row.remove("dropped_at");
+                        row.remove("expires_at");
+                        serde_json::to_value(row).unwrap().to_string()
+                    })
+                    .collect();
+                rows.sort();
+                Outcome::Rows(rows)
+            }
+            Err(err) => Outcome::Error(format!("{:?}", err).split('(').next().unwrap().to_string()),
+        }
+    }
+}
+
+/// Runs the queries of a scenario on a backend, recording what each of them resulted in
+struct Steps {
+    db: Arc<dyn Database>,
+    outcomes: Vec<(String, Outcome)>,
+}
+
+impl Steps {
+    fn run(&mut self, query: Query) {
+        let db = Arc::clone(&self.db);
+        self.record(query, |query| db.execute(query));
+    }
+
+    fn run_coerced(&mut self, coercion: Coercion, query: Query) {
+        let db = Coerced(Arc::clone(&self.db), coercion, false);
+        self.record(query, |query| db.execute(query));
+    }
+
+    fn stream(&mut self, query: Query) {
+        let db = Arc::clone(&self.db);
+        self.record(query, |query| {
+            let mut rows = Vec::new();
+            db.stream(query, &mut |row| {
+                rows.push(row);
+                true
+            })?;
+            Ok(rows)
+        });
+    }
+
+    fn record(
+        &mut self,
+        query: Query,
+        execute: impl FnOnce(Query) -> Result<Vec<ColumnSet>, DobbyError>,
+    ) {
+        let step = format!("#{} {:?}", self.outcomes.len() + 1, query);
+        self.outcomes.push((step, execute(query).into()));
+    }
+}
+
+/// Runs the steps of a scenario
+type Scenario = fn(&mut Steps);
+
+/// The scenarios every backend has to run alike
+const SCENARIOS: [(&str, Scenario); 7] = [
+    ("types", types),
+    ("coercion", coercion_modes),
+    ("errors", errors),
+    ("keys", keys),
+    ("references", references),
+    ("alter", alter),
+    ("drop", drop),
+];
+
+#[test]
+fn conformance() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut differences = Vec::new();
+    for (scenario, run) in SCENARIOS {
+        let outcomes: Vec<_> = BACKENDS
+            .iter()
+            .map(|(backend, create)| {
+                let db = create(dir.path().join(scenario).join(backend));
+                let mut steps = Steps { db, outcomes: Vec::new() };
+                run(&mut steps);
+                (backend, steps.outcomes)
+            })
+            .collect();
+        let (reference, expected) = &outcomes[0];
+        for (backend, actual) in &outcomes[1..] {
+            for ((step, expected), (_, actual)) in expected.iter().zip(actual) {
+                if expected != actual {
+                    differences.push(format!(
+                        "{} {}:\n  {}: {:?}\n  {}: {:?}",
+                        scenario, step, reference, expected, backend, actual
+                    ));
+                }
+            }
+        }
+    }
+    assert!(
+        differences.is_empty(),
+        "The backends differ in {} steps:\n{}",
+        differences.len(),
+        differences.join("\n")
+    );
+}
+
+fn values(values: serde_json::Value) -> ColumnSet {
+    serde_json::from_value(values).unwrap()
+}
+
+fn insert_into(table: &str, row: serde_json::Value) -> Query {
+    Query::Insert { into: table.into(), values: values(row) }
+}
+
+fn select_from(table: &str, conditions: serde_json::Value) -> Query {
+    Query::Select {
+        from: table.into(),
+        columns: vec![],
+        conditions: values(conditions),
+    }
+}
+
+fn alter_table(table: &str) -> Query {
+    Query::Alter {
+        table: table.into(),
+        rename: HashMap::new(),
+        add: vec![],
+        drop: vec![],
+        retype: HashMap::new(),
+        defaults: HashMap::new(),
+        labels: HashMap::new(),
+        precisions: HashMap::new(),
+        generated: HashMap::new(),
+    }
+}
+
+fn types(steps: &mut Steps) {
+    let columns = [
+        ("int", DataType::Int),
+        ("float", DataType::Float),
+        ("char", DataType::Char),
+        ("string", DataType::String),
+        ("char_invl", DataType::CharInvl),
+        ("string_invl", DataType::StringInvl),
+        ("bool", DataType::Bool),
+        ("date", DataType::Date),
+        ("timestamp", DataType::Timestamp),
+        ("int_invl", DataType::IntInvl),
+        ("float_invl", DataType::FloatInvl),
+        ("timestamp_invl", DataType::TimestampInvl),
+        ("bytes", DataType::Bytes),
+        ("json", DataType::Json),
+        ("enum", DataType::Enum),
+        ("decimal", DataType::Decimal),
+        ("uuid", DataType::Uuid),
+    ];
+    let Query::Create {
+        table,
+        columns,
+        auto_increment,
+        defaults,
+        rules,
+        references,
+        generated,
+        ..
+    } = create("types", &columns)
+    else {
+        unreachable!()
+    };
+    steps.run(Query::Create {
+        table,
+        columns,
+        auto_increment,
+        defaults,
+        rules,
+        references,
+        labels: [("enum".into(), vec!["open".into(), "closed".into()])].into(),
+        precisions: [("decimal".into(), Precision { precision: 10, scale: 2 })].into(),
+        generated,
+    });
+
+    let rows = [
+        json!({
+            "int": 1, "float": 1.5, "char": "c", "string": "Dobby",
+            "char_invl": "a..c", "string_invl": ["aa", "cc"], "bool": true,
+            "date": "2024-02-29", "timestamp": "2024-02-29T10:00:00+02:00",
+            "int_invl": [1, 4], "float_invl": "0.5..1.5",
+            "timestamp_invl": "2024-02-29T08:00:00Z..2024-02-29T16:00:00Z",
+            "bytes": "aGk=", "json": {"a": [1, 2], "b": "elf"}, "enum": "open",
+            "decimal": "9.9", "uuid": "0190A5E2-7C3B-7CC1-9A4E-2F1B8D6C9E01",
+        }),
+        json!({
+            "int": -2, "float": -0.25, "char": "d", "string": "it's",
+            "char_invl": ["b", "b"], "string_invl": "a..b", "bool": "false",
+            "date": "1970-01-01", "timestamp": "1970-01-01T00:00:00Z",
+            "int_invl": "-4..-1", "float_invl": [1, 2],
+            "timestamp_invl": ["2024-01-01T00:00:00Z", "2024-12-31T00:00:00Z"],
+            "bytes": "", "json": [null, true], "enum": "closed",
+            "decimal": 12, "uuid": "0190a5e2-7c3b-4cc1-9a4e-2f1b8d6c9e01",
+        }),
+    ];
+    for row in rows.iter() {
+        steps.run(insert_into("types", row.clone()));
+    }
+    steps.run(select_from("types", json!({})));
+    steps.stream(select_from("types", json!({})));
+    for row in rows.iter() {
+        for (column, value) in row.as_object().unwrap() {
+            steps.run(select_from("types", json!({ column: value })));
+        }
+    }
+    steps.run(select_from("types", json!({"json.a.1": 2})));
+    steps.run(select_from("types", json!({"json.b": "elf"})));
+    steps.run(select_from("types", json!({"json.0": null})));
+    steps.run(Query::Select {
+        from: "types".into(),
+        columns: vec!["decimal".into(), "uuid".into()],
+        conditions: values(json!({"int": "1"})),
+    });
+    steps.run(Query::Update {
+        table: "types".into(),
+        set: values(json!({"timestamp": "2024-03-01T00:00:00Z", "decimal": "0.5"})),
+        conditions: values(json!({"bool": false})),
+        all: false,
+    });
+    steps.run(Query::Delete {
+        from: "types".into(),
+        conditions: values(json!({"enum": "open"})),
+        all: false,
+    });
+    steps.run(select_from("types", json!({})));
+}
+
+fn coercion_modes(steps: &mut Steps) {
+    steps.run(create(
+        "test",
+        &[
+            ("id", DataType::Int),
+            ("price", DataType::Float),
+            ("name", DataType::String),
+        ],
+    ));
+    for coercion in [Coercion::Strict, Coercion::Lenient, Coercion::Extended] {
+        for row in [
+            json!({"id": 1, "price": 1.5, "name": "a"}),
+            json!({"id": "2", "price": 2.5, "name": "b"}),
+            json!({"id": 3, "price": 3, "name": "c"}),
+            json!({"id": 4, "price": 4.5, "name": 4}),
+            json!({"id": 5.0, "price": 5.5, "name": "e"}),
+            json!({"id": 6.5, "price": 6.5, "name": "f"}),
+            json!({"id": 7, "price": "NaN", "name": "g"}),
+        ] {
+            steps.run_coerced(coercion, insert_into("test", row));
+        }
+        steps.run_coerced(coercion, select_from("test", json!({"id": "2"})));
+        steps.run_coerced(
+            coercion,
+            Query::Update {
+                table: "test".into(),
+                set: values(json!({"price": 10})),
+                conditions: values(json!({"name": "a"})),
+                all: false,
+            },
+        );
+        steps.run_coerced(
+            coercion,
+            Query::Delete {
+                from: "test".into(),
+                conditions: HashMap::new(),
+                all: true,
+            },
+        );
+    }
+}
+
+fn errors(steps: &mut Steps) {
+    let test = || {
+        create(
+            "test",
+            &[
+                ("id", DataType::Int),
+                ("span", DataType::IntInvl),
+                ("name", DataType::String),
+            ],
+        )
+    };
+    steps.run(test());
+    steps.run(test());
+    steps.run(create("empty", &[]));
+    steps.run(create(
+        "twice",
+        &[("a", DataType::Int), ("a", DataType::String)],
+    ));
+    steps.run(create("bad-name", &[("a", DataType::Int)]));
+    steps.run(Query::Create {
+        table: "ruled".into(),
+        columns: vec![
+            ("age".into(), DataType::Int),
+            ("status".into(), DataType::Enum),
+            ("price".into(), DataType::Decimal),
+        ],
+        auto_increment: None,
+        defaults: HashMap::new(),
+        rules: [("age".into(), vec![Rule::Min(0.0), Rule::Max(150.0)])].into(),
+        references: HashMap::new(),
+        labels: [("status".into(), vec!["open".into()])].into(),
+        precisions: [("price".into(), Precision { precision: 4, scale: 2 })].into(),
+        generated: HashMap::new(),
+    });
+
+    steps.run(insert_into(
+        "test",
+        json!({"id": 1, "span": [1, 2], "name": "a"}),
+    ));
+    steps.run(insert_into("test", json!({"id": 2, "span": [1, 2]})));
+    steps.run(insert_into(
+        "test",
+        json!({"id": 2, "span": [1, 2], "name": "b", "age": 1}),
+    ));
+    steps.run(insert_into(
+        "test",
+        json!({"id": "two", "span": [1, 2], "name": "b"}),
+    ));
+    steps.run(insert_into(
+        "test",
+        json!({"id": 2, "span": [2, 1], "name": "b"}),
+    ));
+    steps.run(insert_into("missing", json!({"id": 1})));
+    steps.run(insert_into(
+        "ruled",
+        json!({"age": 151, "status": "open", "price": 1}),
+    ));
+    steps.run(insert_into(
+        "ruled",
+        json!({"age": 1, "status": "closed", "price": 1}),
+    ));
+    steps.run(insert_into(
+        "ruled",
+        json!({"age": 1, "status": "open", "price": "100.5"}),
+    ));
+    steps.run(insert_into(
+        "ruled",
+        json!({"age": 1, "status": "open", "price": "1.005"}),
+    ));
+    steps.run(insert_into(
+        "ruled",
+        json!({"age": 1, "status": "open", "price": "1.5"}),
+    ));
+
+    steps.run(select_from("missing", json!({})));
+    steps.run(select_from("test", json!({"age": 1})));
+    steps.run(select_from("test", json!({"id": "one"})));
+    steps.run(select_from("test", json!({"name.first": "a"})));
+    steps.run(Query::Select {
+        from: "test".into(),
+        columns: vec!["age".into()],
+        conditions: HashMap::new(),
+    });
+    steps.run(Query::Update {
+        table: "test".into(),
+        set: values(json!({"age": 1})),
+        conditions: HashMap::new(),
+        all: true,
+    });
+    steps.run(Query::Update {
+        table: "ruled".into(),
+        set: values(json!({"age": -1})),
+        conditions: HashMap::new(),
+        all: true,
+    });
+    // nothing to set
+    steps.run(Query::Update {
+        table: "test".into(),
+        set: HashMap::new(),
+        conditions: values(json!({"id": 1})),
+        all: false,
+    });
+    steps.run(Query::Update {
+        table: "test".into(),
+        set: HashMap::new(),
+        conditions: values(json!({"id": "one"})),
+        all: false,
+    });
+    steps.run(Query::Delete {
+        from: "missing".into(),
+        conditions: HashMap::new(),
+        all: true,
+    });
+    steps.run(Query::Rename { table: "test".into(), to: "ruled".into() });
+    steps.run(Query::Rename { table: "missing".into(), to: "other".into() });
+    steps.run(Query::Drop { table: "missing".into() });
+    steps.run(Query::Undrop { table: "missing".into() });
+    steps.run(Query::Purge { table: Some("missing".into()) });
+    steps.run(select_from("test", json!({})));
+    steps.run(select_from("ruled", json!({})));
+}
+
+fn keys(steps: &mut Steps) {
+    let mut users = create(
+        "users",
+        &[("id", DataType::Int), ("name", DataType::String)],
+    );
+    if let Query::Create { auto_increment, .. } = &mut users {
+        *auto_increment = Some("id".into());
+    }
+    steps.run(users);
+    steps.run(insert_into("users", json!({"name": "Harry"})));
+    steps.run(insert_into("users", json!({"name": "Ron"})));
+    // a key can't be given to a second row, on insert or update
+    steps.run(insert_into("users", json!({"id": 1, "name": "Hermione"})));
+    steps.run(Query::Update {
+        table: "users".into(),
+        set: values(json!({"id": 1})),
+        conditions: values(json!({"name": "Ron"})),
+        all: false,
+    });
+    steps.run(Query::Update {
+        table: "users".into(),
+        set: values(json!({"id": 5})),
+        conditions: HashMap::new(),
+        all: true,
+    });
+    // but a row can keep its own
+    steps.run(Query::Update {
+        table: "users".into(),
+        set: values(json!({"id": 1, "name": "Harry Potter"})),
+        conditions: values(json!({"id": 1})),
+        all: false,
+    });
+    // keys set explicitly move the counter past them
+    steps.run(Query::Update {
+        table: "users".into(),
+        set: values(json!({"id": 10})),
+        conditions: values(json!({"name": "Ron"})),
+        all: false,
+    });
+    steps.run(insert_into("users", json!({"name": "Hermione"})));
+    steps.run(insert_into("users", json!({"id": 20, "name": "Ginny"})));
+    steps.run(insert_into("users", json!({"name": "Luna"})));
+    steps.run(select_from("users", json!({})));
+}
+
+fn references(steps: &mut Steps) {
+    let Query::Create {
+        table,
+        columns,
+        defaults,
+        rules,
+        labels,
+        precisions,
+        generated,
+        ..
+    } = create(
+        "users",
+        &[("id", DataType::Int), ("name", DataType::String)],
+    )
+    else {
+        unreachable!()
+    };
+    steps.run(Query::Create {
+        table,
+        columns,
+        auto_increment: Some("id".into()),
+        defaults,
+        rules,
+        references: HashMap::new(),
+        labels,
+        precisions,
+        generated,
+    });
+    for (table, on_delete) in [("cars", OnDelete::Restrict), ("pets", OnDelete::Cascade)] {
+        let Query::Create {
+            table,
+            columns,
+            defaults,
+            rules,
+            labels,
+            precisions,
+            generated,
+            ..
+        } = create(table, &[("id", DataType::Int), ("owner", DataType::Int)])
+        else {
+            unreachable!()
+        };
+        let reference = Reference {
+            table: "users".into(),
+            column: "id".into(),
+            on_delete,
+        };
+        steps.run(Query::Create {
+            table,
+            columns,
+            auto_increment: Some("id".into()),
+            defaults,
+            rules,
+            references: [("owner".into(), reference)].into(),
+            labels,
+            precisions,
+            generated,
+        });
+    }
+
+    for name in ["Harry", "Ron", "Hermione"] {
+        steps.run(insert_into("users", json!({ "name": name })));
+    }
+    steps.run(insert_into("cars", json!({"owner": 1})));
+    steps.run(insert_into("cars", json!({"owner": 4})));
+    steps.run(insert_into("pets", json!({"owner": 2})));
+    steps.run(insert_into("pets", json!({"owner": 2})));
+    steps.run(insert_into("pets", json!({"owner": 3})));
+    // altering the referenced table keeps the references and the counter
+    let mut alter_users = alter_table("users");
+    if let Query::Alter { add, defaults, .. } = &mut alter_users {
+        add.push(("age".into(), DataType::Int));
+        defaults.insert("age".into(), 11.into());
+    }
+    steps.run(alter_users);
+    steps.run(select_from("pets", json!({})));
+    steps.run(insert_into("pets", json!({"owner": 7})));
+
+    steps.run(Query::Update {
+        table: "cars".into(),
+        set: values(json!({"owner": 5})),
+        conditions: HashMap::new(),
+        all: true,
+    });
+    // keys of referenced rows can't change, but can be set to what they are
+    steps.run(Query::Update {
+        table: "users".into(),
+        set: values(json!({"id": 2})),
+        conditions: values(json!({"id": 2})),
+        all: false,
+    });
+    steps.run(Query::Update {
+        table: "users".into(),
+        set: values(json!({"id": 20})),
+        conditions: values(json!({"id": 2})),
+        all: false,
+    });
+    steps.run(select_from("users", json!({"id": 2})));
+    steps.run(Query::Delete {
+        from: "users".into(),
+        conditions: values(json!({"name": "Harry"})),
+        all: false,
+    });
+    steps.run(Query::Delete {
+        from: "users".into(),
+        conditions: values(json!({"name": "Ron"})),
+        all: false,
+    });
+    steps.run(select_from("pets", json!({})));
+    steps.run(Query::Drop { table: "users".into() });
+    steps.run(insert_into("users", json!({"id": 10, "name": "Ginny"})));
+    steps.run(insert_into("users", json!({"name": "Luna"})));
+    steps.run(select_from("users", json!({})));
+}
+
+fn alter(steps: &mut Steps) {
+    steps.run(create(
+        "test",
+        &[
+            ("id", DataType::Int),
+            ("price", DataType::Float),
+            ("name", DataType::String),
+        ],
+    ));
+    steps.run(alter_table("test"));
+    for (id, price) in [(1, 1.0), (2, 2.5)] {
+        steps.run(insert_into(
+            "test",
+            json!({"id": id, "price": price, "name": "a"}),
+        ));
+    }
+
+    let mut steps_on = |change: fn(&mut Query)| {
+        let mut query = alter_table("test");
+        change(&mut query);
+        steps.run(query);
+        steps.run(select_from("test", json!({})));
+    };
+    steps_on(|query| {
+        let Query::Alter { rename, .. } = query else {
+            unreachable!()
+        };
+        rename.insert("name".into(), "title".into());
+    });
+    steps_on(|query| {
+        let Query::Alter { add, .. } = query else {
+            unreachable!()
+        };
+        add.push(("count".into(), DataType::Int));
+    });
+    steps_on(|query| {
+        let Query::Alter { add, defaults, .. } = query else {
+            unreachable!()
+        };
+        add.push(("count".into(), DataType::Int));
+        defaults.insert("count".into(), "7".into());
+    });
+    steps_on(|query| {
+        let Query::Alter { rename, add, .. } = query else {
+            unreachable!()
+        };
+        rename.insert("count".into(), "id".into());
+        add.push(("other".into(), DataType::Int));
+    });
+    steps_on(|query| {
+        let Query::Alter { drop, .. } = query else {
+            unreachable!()
+        };
+        drop.push("missing".into());
+    });
+    steps_on(|query| {
+        let Query::Alter { drop, .. } = query else {
+            unreachable!()
+        };
+        drop.push("count".into());
+    });
+    steps_on(|query| {
+        let Query::Alter { retype, .. } = query else {
+            unreachable!()
+        };
+        retype.insert("price".into(), DataType::Int);
+    });
+    steps_on(|query| {
+        let Query::Alter { retype, .. } = query else {
+            unreachable!()
+        };
+        retype.insert("price".into(), DataType::String);
+    });
+    steps_on(|query| {
+        let Query::Alter { retype, .. } = query else {
+            unreachable!()
+        };
+        retype.insert("id".into(), DataType::Decimal);
+    });
+    steps.run(select_from("test", json!({"price": "2.5", "id": 2})));
+    steps.run(insert_into(
+        "test",
+        json!({"id": "3.25", "price": 3, "title": "b"}),
+    ));
+    steps.run(select_from("test", json!({"id": "3.250"})));
+
+    // the stored values and the constraints of the columns follow their new datatypes
+    let mut docs = create("docs", &[("doc", DataType::Json), ("kind", DataType::Enum)]);
+    if let Query::Create { labels, .. } = &mut docs {
+        labels.insert("kind".into(), vec!["book".into(), "film".into()]);
+    }
+    steps.run(docs);
+    steps.run(insert_into(
+        "docs",
+        json!({"doc": {"a": [1, 2]}, "kind": "book"}),
+    ));
+    let mut retype_docs = alter_table("docs");
+    if let Query::Alter { retype, .. } = &mut retype_docs {
+        retype.insert("doc".into(), DataType::String);
+        retype.insert("kind".into(), DataType::String);
+    }
+    steps.run(retype_docs);
+    steps.run(insert_into(
+        "docs",
+        json!({"doc": "plain text", "kind": "song"}),
+    ));
+    steps.run(select_from("docs", json!({})));
+    steps.run(select_from("docs", json!({"doc": "{\"a\":[1,2]}"})));
+    steps.run(select_from("docs", json!({"kind": "book"})));
+
+    // added columns can have any attributes, and keep their defaults for later inserts
+    let mut add_columns = alter_table("docs");
+    if let Query::Alter { add, defaults, labels, precisions, generated, .. } = &mut add_columns {
+        add.push(("rating".into(), DataType::Enum));
+        add.push(("price".into(), DataType::Decimal));
+        add.push(("ref".into(), DataType::Uuid));
+        defaults.insert("rating".into(), "good".into());
+        defaults.insert("price".into(), "1.5".into());
+        labels.insert("rating".into(), vec!["good".into(), "bad".into()]);
+        precisions.insert("price".into(), Precision { precision: 5, scale: 2 });
+        generated.insert("ref".into(), UuidVersion::V4);
+    }
+    steps.run(add_columns);
+    steps.run(insert_into(
+        "docs",
+        json!({"doc": "x", "kind": "y", "rating": "bad", "ref": "67e55044-10b1-426f-9247-bb680e5fe0c8"}),
+    ));
+    steps.run(Query::Select {
+        from: "docs".into(),
+        columns: vec!["doc".into(), "rating".into(), "price".into()],
+        conditions: values(json!({"price": "1.50"})),
+    });
+    let mut stray_default = alter_table("docs");
+    if let Query::Alter { add, defaults, .. } = &mut stray_default {
+        add.push(("extra".into(), DataType::Int));
+        defaults.insert("kind".into(), "z".into());
+    }
+    steps.run(stray_default);
+
+    // only the rows a table already has need values for an added column
+    steps.run(create("empty", &[("id", DataType::Int)]));
+    let mut add_required = alter_table("empty");
+    if let Query::Alter { add, .. } = &mut add_required {
+        add.push(("name".into(), DataType::String));
+    }
+    steps.run(add_required.clone());
+    steps.run(insert_into("empty", json!({"id": 1, "name": "a"})));
+    if let Query::Alter { add, .. } = &mut add_required {
+        add[0].0 = "other".into();
+    }
+    steps.run(add_required);
+    steps.run(select_from("empty", json!({})));
+}
+
+fn drop(steps: &mut Steps) {
+    let test = || create("test", &[("id", DataType::Int)]);
+    steps.run(test());
+    steps.run(insert_into("test", json!({"id": 1})));
+    steps.run(Query::Drop { table: "test".into() });
+    steps.run(select_from("test", json!({})));
+    steps.run(Query::ListTrash);
+    steps.run(test());
+    steps.run(insert_into("test", json!({"id": 2})));
+    steps.run(Query::Undrop { table: "test".into() });
+    steps.run(Query::Rename { table: "test".into(), to: "other".into() });
+    steps.run(Query::Undrop { table: "test".into() });
+    steps.run(select_from("test", json!({})));
+    steps.run(select_from("other", json!({})));
+    steps.run(Query::Drop { table: "test".into() });
+    steps.run(Query::Drop { table: "other".into() });
+    // a table can't replace a dropped one with the same name in the trash
+    steps.run(test());
+    steps.run(Query::Drop { table: "test".into() });
+    steps.run(select_from("test", json!({})));
+    steps.run(Query::ListTrash);
+    steps.run(Query::Purge { table: Some("test".into()) });
+    steps.run(Query::ListTrash);
+    steps.run(Query::Purge { table: None });
+    steps.run(Query::ListTrash);
+    steps.run(Query::Undrop { table: "other".into() });
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn existing_sqlite_file() -> Result<(), DobbyError> {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("shop.sqlite");
+    rusqlite::Connection::open(&file)?.execute_batch(
+        "CREATE TABLE goods (
+            id INTEGER PRIMARY KEY,
+            name VARCHAR(40) NOT NULL,
+            price DECIMAL(10, 2) NOT NULL DEFAULT 0,
+            note TEXT NOT NULL DEFAULT 'it''s new',
+            added DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            comment TEXT,
+            shape GEOMETRY NOT NULL DEFAULT 'point',
+            \"in stock\" BOOLEAN NOT NULL DEFAULT 1,
+            \"all\" BOOLEAN NOT NULL DEFAULT 0
+        );
+        CREATE TABLE \"old-goods\" (id INTEGER);
+        CREATE TABLE codes (code TEXT PRIMARY KEY, name TEXT NOT NULL) WITHOUT ROWID;
+        CREATE INDEX goods_by_name ON goods (name);
+        CREATE TABLE sales (id INTEGER PRIMARY KEY, sold DATETIME NOT NULL);
+        INSERT INTO goods (name, price, note, added) VALUES ('Socks', 9.9, 'warm', '2024-02-29 10:00:00');
+        INSERT INTO goods (name, price, comment, added) VALUES ('Hat', 5, 'woolen', '2024-02-29 11:00:00');
+        INSERT INTO sales (sold) VALUES (1709200800);",
+    )?;
+
+    let db = sqlite::Sqlite::introspect(file.clone());
+    let reasons = |table: &str| {
+        let mut reasons: Vec<_> = db.left_out()[table]
+            .iter()
+            .map(|(column, reason)| format!("{}: {}", column, reason))
+            .collect();
+        reasons.sort();
+        reasons
+    };
+    assert_eq!(
+        reasons("goods"),
+        vec![
+            "all: reserved name, taken by a flag of REST query strings",
+            "comment: nullable",
+            "in stock: invalid name",
+            "shape: unknown type \"GEOMETRY\"",
+        ]
+    );
+    // unix times can't be told from ints
+    assert_eq!(reasons("sales"), vec!["sold: timestamps stored as integer"]);
+    let db = Arc::new(Mutex::new(db)) as Arc<dyn Database>;
+    let schema = db.schema();
+    assert_eq!(
+        schema.tables["goods"],
+        vec![
+            ("added".into(), DataType::Timestamp),
+            ("id".into(), DataType::Int),
+            ("name".into(), DataType::String),
+            ("note".into(), DataType::String),
+            ("price".into(), DataType::Decimal),
+        ]
+    );
+    assert!(!schema.tables.contains_key("old-goods"));
+    // it couldn't be read a page at a time
+    assert!(!schema.tables.contains_key("codes"));
+    assert_eq!(
+        schema.auto_increment("goods").map(|(column, _)| column),
+        Some("id".into())
+    );
+
+    let select = |conditions: serde_json::Value| {
+        db.execute(Query::Select {
+            from: "goods".into(),
+            columns: vec!["id".into(), "name".into(), "price".into(), "added".into()],
+            conditions: values(conditions),
+        })
+    };
+    assert_eq!(
+        serde_json::to_value(select(json!({"name": "Socks"}))?).unwrap(),
+        json!([{"id": 1, "name": "Socks", "price": "9.9", "added": "2024-02-29T10:00:00Z"}])
+    );
+    assert_eq!(
+        db.execute(select_from("goods", json!({"name": "Hat"})))?
+            .len(),
+        1
+    );
+    for column in ["comment", "shape", "in stock", "all"] {
+        assert!(matches!(
+            select(json!({ column: "x" })),
+            Err(DobbyError::Unsupported(_))
+        ));
+    }
+    assert_eq!(db.execute(select_from("sales", json!({})))?.len(), 1);
+    assert!(matches!(
+        db.execute(select_from(
+            "sales",
+            json!({"sold": "2024-02-29T10:00:00Z"})
+        )),
+        Err(DobbyError::Unsupported(_))
+    ));
+
+    // timestamps are compared in the format SQLite's datetime() stores them in
+    assert_eq!(
+        db.execute(select_from(
+            "goods",
+            json!({"added": "2024-02-29T11:00:00Z"})
+        ))?[0]["name"],
+        TypedValue::String("Hat".into())
+    );
+    assert!(db
+        .execute(select_from(
+            "goods",
+            json!({"added": "2024-02-29T11:00:00.5Z"})
+        ))?
+        .is_empty());
+    assert!(matches!(
+        db.execute(Query::Insert {
+            into: "goods".into(),
+            values: values(json!({"name": "Gloves", "added": "2024-03-01T00:00:00.5Z"})),
+        }),
+        Err(DobbyError::InvalidValue(_, DataType::Timestamp))
+    ));
+
+    let inserted = db.execute(Query::Insert {
+        into: "goods".into(),
+        values: values(json!({"name": "Scarf", "added": "2024-03-01T00:00:00Z"})),
+    })?;
+    assert_eq!(
+        serde_json::to_value(inserted).unwrap(),
+        json!([{
+            "id": 3,
+            "name": "Scarf",
+            "price": "0",
+            "note": "it's new",
+            "added": "2024-03-01T00:00:00Z",
+        }])
+    );
+    let added: String = rusqlite::Connection::open(&file)?.query_row(
+        "SELECT added FROM goods WHERE name = 'Scarf'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(added, "2024-03-01 00:00:00");
+
+    // and still are once the table is renamed
+    db.execute(Query::Rename { table: "goods".into(), to: "wares".into() })?;
+    assert_eq!(
+        db.execute(select_from(
+            "wares",
+            json!({"added": "2024-03-01T00:00:00Z"})
+        ))?
+        .len(),
+        1
+    );
+
+    // rebuilding the table would lose what dobby doesn't know of, like its index
+    let mut alter = alter_table("wares");
+    if let Query::Alter { add, defaults, .. } = &mut alter {
+        add.push(("color".into(), DataType::String));
+        defaults.insert("color".into(), "red".into());
+    }
+    assert!(matches!(db.execute(alter), Err(DobbyError::Unsupported(_))));
+    let mut alter = alter_table("wares");
+    if let Query::Alter { rename, .. } = &mut alter {
+        rename.insert("note".into(), "remark".into());
+    }
+    db.execute(alter)?;
+    let indexes: i64 = rusqlite::Connection::open(&file)?.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'index' AND tbl_name = 'wares'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(indexes, 1);
+
+    // dropped tables are still in the trash once the file is opened again
+    db.execute(Query::Drop { table: "sales".into() })?;
+    db.execute(create("sales", &[("id", DataType::Int)]))?;
+    std::mem::drop(db);
+    let db = Arc::new(Mutex::new(sqlite::Sqlite::introspect(file))) as Arc<dyn Database>;
+    let trash = db.execute(Query::ListTrash)?;
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0]["table"], TypedValue::String("sales".into()));
+    assert!(db.schema().tables.contains_key("sales"));
+    assert!(matches!(
+        db.execute(Query::Drop { table: "sales".into() }),
+        Err(DobbyError::TableInTrash(_))
+    ));
+    db.execute(Query::Purge { table: Some("sales".into()) })?;
+    db.execute(Query::Drop { table: "sales".into() })?;
+    db.execute(Query::Undrop { table: "sales".into() })?;
+    assert!(db.execute(select_from("sales", json!({})))?.is_empty());
+    Ok(())
+}
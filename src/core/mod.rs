@@ -3,4 +3,7 @@ pub mod schema;
 pub mod table;
 pub mod types;
 
-pub use database::{dobby::Dobby, sqlite::Sqlite, Database};
+pub use database::{dobby::Dobby, Database};
+
+#[cfg(feature = "sqlite")]
+pub use database::sqlite::Sqlite;
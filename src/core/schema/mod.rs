@@ -1,15 +1,22 @@
 use super::types::DataType;
-use super::types::DobbyError;
+use super::types::{
+    json_path_segments, split_json_path, ColumnSet, DobbyError, Precision, Reference, Rule,
+    TypedValue, UuidVersion,
+};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{hash_map::Entry, HashMap};
 use std::fs::File;
 use std::io::{self, BufRead, Write};
 use std::path::Path;
+use std::time::Duration;
 
 #[cfg(test)]
 mod tests;
 
+/// Names taken by the flags of REST query strings, where the other parameters are conditions
+pub const RESERVED_COLUMNS: [&str; 1] = ["all"];
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize)]
 #[serde(rename_all = "lowercase")]
 enum SchemaKind {
@@ -21,10 +28,50 @@ enum SchemaKind {
 pub struct Schema {
     #[serde(serialize_with = "serialize_tables")]
     pub tables: HashMap<String, Vec<(String, DataType)>>,
+    /// Attributes of the tables' columns, for the columns that have any
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub attributes: HashMap<String, HashMap<String, Attributes>>,
+    #[serde(skip)]
+    pub trash: HashMap<String, DroppedTable>,
     name: String,
     kind: SchemaKind,
 }
 
+/// Column attributes beyond the datatype
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Attributes {
+    /// The next value of an auto-increment column
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_increment: Option<i64>,
+    /// The value to insert when the column is omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<TypedValue>,
+    /// Constraints every value of the column has to meet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// The key column of another table, which every value has to match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub references: Option<Reference>,
+    /// The values an enum column can take, stored by their index
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// The digits of a decimal column, which can otherwise hold any decimal
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+    /// The version of the uuids generated when the column is omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generate: Option<UuidVersion>,
+}
+
+/// A table that was dropped, but can still be restored
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedTable {
+    pub columns: Vec<(String, DataType)>,
+    pub attributes: HashMap<String, Attributes>,
+    /// Unix timestamp of the drop, in seconds
+    pub dropped_at: u64,
+}
+
 fn serialize_tables<S: serde::Serializer>(
     tables: &HashMap<String, Vec<(String, DataType)>>,
     serializer: S,
@@ -42,6 +89,8 @@ impl Schema {
     pub fn new_sqlite(name: String) -> Self {
         Schema {
             tables: HashMap::new(),
+            attributes: HashMap::new(),
+            trash: HashMap::new(),
             name,
             kind: SchemaKind::Sqlite,
         }
@@ -50,6 +99,8 @@ impl Schema {
     pub fn new_dobby(name: String) -> Self {
         Schema {
             tables: HashMap::new(),
+            attributes: HashMap::new(),
+            trash: HashMap::new(),
             name,
             kind: SchemaKind::Dobby,
         }
@@ -68,6 +119,8 @@ impl Schema {
         let file = File::open(path.join(".schema")).expect("Schema file not found");
         let mut reader = io::BufReader::new(file).lines();
         let mut tables = HashMap::new();
+        let mut attributes = HashMap::new();
+        let mut trash = HashMap::new();
         let header = reader
             .next()
             .expect("Schema file is empty")
@@ -75,16 +128,58 @@ impl Schema {
         let (name, kind) = header.split_once(':').expect("Schema file corrupted");
         for line in reader {
             let line = line.expect("Failed to read schema file");
+            // column attributes are stored as `@table#json`
+            if let Some(line) = line.strip_prefix('@') {
+                let (table, json) = line.split_once('#').expect("Schema file corrupted");
+                let table_attributes: HashMap<String, Attributes> =
+                    serde_json::from_str(json).expect("Schema file corrupted");
+                attributes.insert(table.to_string(), table_attributes);
+                continue;
+            }
             let (table, columns) = line.split_once('#').expect("Schema file corrupted");
-            for column in columns.split(',') {
-                let (column, data_type) = column.split_once(':').expect("Schema file corrupted");
-                tables
-                    .entry(table.to_string())
-                    .or_insert_with(Vec::new)
-                    .push((
+            let columns: Vec<_> = columns
+                .split(',')
+                .map(|column| {
+                    let (column, data_type) =
+                        column.split_once(':').expect("Schema file corrupted");
+                    (
                         column.to_string(),
                         data_type.try_into().expect("Schema file corrupted"),
-                    ));
+                    )
+                })
+                .collect();
+
+            // dropped tables are stored as `~table:dropped_at#columns`
+            if let Some(table) = table.strip_prefix('~') {
+                let (table, dropped_at) = table.split_once(':').expect("Schema file corrupted");
+                let dropped_at = dropped_at.parse().expect("Schema file corrupted");
+                let dropped = DroppedTable { columns, attributes: HashMap::new(), dropped_at };
+                trash.insert(table.to_string(), dropped);
+            } else {
+                tables.insert(table.to_string(), columns);
+            }
+        }
+        // JSON doesn't tell chars from strings, so the defaults get their datatypes back
+        for (table, table_attributes) in attributes.iter_mut() {
+            let columns = match table.strip_prefix('~') {
+                Some(table) => &trash[table].columns,
+                None => &tables[table],
+            };
+            for (column, data_type) in columns {
+                if let Some(default) = table_attributes
+                    .get_mut(column)
+                    .and_then(|attributes| attributes.default.as_mut())
+                {
+                    *default = default
+                        .clone()
+                        .coerce(*data_type)
+                        .expect("Schema file corrupted");
+                }
+            }
+        }
+        for (table, dropped) in trash.iter_mut() {
+            if let Some(table_attributes) = attributes.remove(&format!("~{}", table)) {
+                dropped.attributes = table_attributes;
             }
         }
         let kind = match kind {
@@ -92,7 +187,7 @@ impl Schema {
             "sqlite" => SchemaKind::Sqlite,
             _ => panic!("Schema file corrupted"),
         };
-        Schema { tables, name: name.into(), kind }
+        Schema { tables, attributes, trash, name: name.into(), kind }
     }
 
     pub fn dump(&self, path: &Path) -> Result<(), io::Error> {
@@ -101,13 +196,34 @@ impl Schema {
         file.write_all(self.name.as_bytes())?;
         file.write_all(format!(":{:?}", self.kind).to_lowercase().as_bytes())?;
         file.write_all(b"\n")?;
-        for (table, columns) in &self.tables {
-            let table_schema: String = columns
+        let table_schema = |columns: &[(String, DataType)]| {
+            columns
                 .iter()
                 .map(|(column, data_type)| format!("{}:{:?}", column, data_type))
                 .collect::<Vec<_>>()
-                .join(",");
-            file.write_all(format!("{}#{}\n", table, table_schema).as_bytes())?;
+                .join(",")
+        };
+        for (table, columns) in &self.tables {
+            file.write_all(format!("{}#{}\n", table, table_schema(columns)).as_bytes())?;
+        }
+        for (table, DroppedTable { columns, dropped_at, .. }) in &self.trash {
+            let line = format!("~{}:{}#{}\n", table, dropped_at, table_schema(columns));
+            file.write_all(line.as_bytes())?;
+        }
+        let trashed_attributes = self
+            .trash
+            .iter()
+            .map(|(table, dropped)| (format!("~{}", table), &dropped.attributes));
+        for (table, attributes) in self
+            .attributes
+            .iter()
+            .map(|(table, attributes)| (table.clone(), attributes))
+            .chain(trashed_attributes)
+        {
+            if !attributes.is_empty() {
+                let json = serde_json::to_string(attributes).map_err(io::Error::from)?;
+                file.write_all(format!("@{}#{}\n", table, json).as_bytes())?;
+            }
         }
         Ok(())
     }
@@ -124,7 +240,7 @@ impl Schema {
         if let Entry::Vacant(entry) = self.tables.entry(name.clone()) {
             columns.sort();
             for (i, (column, _)) in columns.iter().enumerate() {
-                Self::validate_name(column)?;
+                Self::validate_column_name(column)?;
                 if i > 0 && column == &columns[i - 1].0 {
                     return Err(DobbyError::ColumnAlreadyExists(column.clone(), name));
                 }
@@ -136,13 +252,87 @@ impl Schema {
         }
     }
 
-    pub fn drop_table(&mut self, name: String) -> Result<(), DobbyError> {
-        if let Entry::Occupied(entry) = self.tables.entry(name.clone()) {
-            entry.remove();
-            Ok(())
-        } else {
-            Err(DobbyError::TableNotFound(name))
+    /// Moves the table to the trash, unless a dropped table with the same name is there
+    pub fn trash_table(&mut self, name: String, dropped_at: u64) -> Result<(), DobbyError> {
+        if self.trash.contains_key(&name) {
+            return Err(DobbyError::TableInTrash(name));
+        }
+        if let Some((child, _, _)) = self
+            .references_to(&name)
+            .into_iter()
+            .find(|(t, _, _)| t != &name)
+        {
+            return Err(DobbyError::Referenced(name, child));
+        }
+        let columns = self
+            .tables
+            .remove(&name)
+            .ok_or_else(|| DobbyError::TableNotFound(name.clone()))?;
+        let attributes = self.attributes.remove(&name).unwrap_or_default();
+        self.trash
+            .insert(name, DroppedTable { columns, attributes, dropped_at });
+        Ok(())
+    }
+
+    pub fn restore_table(&mut self, name: String) -> Result<(), DobbyError> {
+        if self.tables.contains_key(&name) {
+            return Err(DobbyError::TableAlreadyExists(name));
+        }
+        let dropped = self
+            .trash
+            .get(&name)
+            .ok_or_else(|| DobbyError::TableNotFound(name.clone()))?;
+        // the referenced tables could have been dropped in the meantime
+        for (column, attributes) in &dropped.attributes {
+            if let Some(reference) = &attributes.references {
+                let key = self.auto_increment(&reference.table).map(|(key, _)| key);
+                if reference.table != name && key.as_ref() != Some(&reference.column) {
+                    return Err(DobbyError::InvalidReference(
+                        column.clone(),
+                        reference.to_string(),
+                    ));
+                }
+            }
+        }
+        let dropped = self.trash.remove(&name).unwrap();
+        if !dropped.attributes.is_empty() {
+            self.attributes.insert(name.clone(), dropped.attributes);
+        }
+        self.tables.insert(name, dropped.columns);
+        Ok(())
+    }
+
+    /// Removes the table from the trash for good
+    pub fn purge_table(&mut self, name: String) -> Result<DroppedTable, DobbyError> {
+        self.trash
+            .remove(&name)
+            .ok_or(DobbyError::TableNotFound(name))
+    }
+
+    /// Dropped tables that have been in the trash longer than `retention`
+    pub fn expired_tables(&self, retention: Duration, now: u64) -> Vec<String> {
+        self.trash
+            .iter()
+            .filter(|(_, dropped)| dropped.dropped_at + retention.as_secs() <= now)
+            .map(|(table, _)| table.clone())
+            .collect()
+    }
+
+    pub fn rename_table(&mut self, table: String, to: String) -> Result<(), DobbyError> {
+        Self::validate_name(&to)?;
+        if self.tables.contains_key(&to) {
+            return Err(DobbyError::TableAlreadyExists(to));
+        }
+        let columns = self
+            .tables
+            .remove(&table)
+            .ok_or(DobbyError::TableNotFound(table.clone()))?;
+        if let Some(attributes) = self.attributes.remove(&table) {
+            self.attributes.insert(to.clone(), attributes);
         }
+        self.retarget_references(&table, &to, &HashMap::new());
+        self.tables.insert(to, columns);
+        Ok(())
     }
 
     pub fn alter_table(
@@ -150,12 +340,13 @@ impl Schema {
         table: String,
         mut rename: HashMap<String, String>,
     ) -> Result<(), DobbyError> {
+        let renamed = rename.clone();
         if let Entry::Occupied(mut entry) = self.tables.entry(table.clone()) {
             let mut new_columns = Vec::new();
 
             for (column, data_type) in entry.get().iter() {
                 let new_column = if rename.contains_key(column) {
-                    Self::validate_name(&rename[column])?;
+                    Self::validate_column_name(&rename[column])?;
                     rename.remove(column).unwrap()
                 } else {
                     column.clone()
@@ -173,6 +364,16 @@ impl Schema {
                 ))
             } else {
                 entry.insert(new_columns);
+                if let Some(attributes) = self.attributes.get_mut(&table) {
+                    *attributes = attributes
+                        .drain()
+                        .map(|(column, value)| match renamed.get(&column) {
+                            Some(new_column) => (new_column.clone(), value),
+                            None => (column, value),
+                        })
+                        .collect();
+                }
+                self.retarget_references(&table, &table, &renamed);
                 Ok(())
             }
         } else {
@@ -180,11 +381,622 @@ impl Schema {
         }
     }
 
-    fn validate_name(name: &str) -> Result<(), DobbyError> {
-        if name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+    /// Adds the columns along with their attributes, which can't be given to other columns.
+    /// The defaults are kept for later inserts too.
+    pub fn add_columns(
+        &mut self,
+        table: String,
+        columns: Vec<(String, DataType)>,
+        defaults: ColumnSet,
+        labels: HashMap<String, Vec<String>>,
+        precisions: HashMap<String, Precision>,
+        generated: HashMap<String, UuidVersion>,
+    ) -> Result<(), DobbyError> {
+        let Entry::Occupied(mut entry) = self.tables.entry(table.clone()) else {
+            return Err(DobbyError::TableNotFound(table));
+        };
+        if let Some(column) = (defaults.keys())
+            .chain(labels.keys())
+            .chain(precisions.keys())
+            .chain(generated.keys())
+            .find(|column| !columns.iter().any(|(c, _)| c == *column))
+        {
+            return Err(DobbyError::ColumnNotFound(column.clone(), table));
+        }
+        for (column, data_type) in &columns {
+            Self::validate_column_name(column)?;
+            if entry.get().iter().any(|(c, _)| c == column) {
+                return Err(DobbyError::ColumnAlreadyExists(column.clone(), table));
+            }
+            let column_labels = labels.get(column);
+            match (data_type, column_labels) {
+                (DataType::Enum, Some(column_labels)) if valid_labels(column_labels) => {}
+                (DataType::Enum, _) | (_, Some(_)) => {
+                    return Err(DobbyError::InvalidLabels(column.clone()));
+                }
+                _ => {}
+            }
+        }
+        entry.get_mut().extend(columns);
+        for (column, column_labels) in labels {
+            self.attributes
+                .entry(table.clone())
+                .or_default()
+                .entry(column)
+                .or_default()
+                .labels = column_labels;
+        }
+        self.set_precisions(&table, precisions)?;
+        self.set_generated(&table, generated)?;
+        self.set_defaults(&table, defaults)
+    }
+
+    pub fn drop_columns(&mut self, table: String, columns: Vec<String>) -> Result<(), DobbyError> {
+        for (child, _, reference) in self.references_to(&table) {
+            if columns.contains(&reference.column) {
+                return Err(DobbyError::Referenced(table, child));
+            }
+        }
+        if let Entry::Occupied(mut entry) = self.tables.entry(table.clone()) {
+            for column in columns {
+                match entry.get().iter().position(|(c, _)| c == &column) {
+                    Some(index) => entry.get_mut().remove(index),
+                    None => return Err(DobbyError::ColumnNotFound(column, table)),
+                };
+                if let Some(attributes) = self.attributes.get_mut(&table) {
+                    attributes.remove(&column);
+                }
+            }
+            if entry.get().is_empty() {
+                return Err(DobbyError::NoColumns);
+            }
+            Ok(())
+        } else {
+            Err(DobbyError::TableNotFound(table))
+        }
+    }
+
+    pub fn retype_columns(
+        &mut self,
+        table: String,
+        retype: HashMap<String, DataType>,
+    ) -> Result<(), DobbyError> {
+        for (column, data_type) in &retype {
+            let attributes = self.column_attributes(&table, column);
+            if *data_type == DataType::Enum && attributes.labels.is_empty() {
+                return Err(DobbyError::InvalidLabels(column.clone()));
+            }
+            if attributes.auto_increment.is_some() && *data_type != DataType::Int {
+                return Err(DobbyError::InvalidAutoIncrement(column.clone(), table));
+            }
+            if let Some(reference) = attributes
+                .references
+                .filter(|_| *data_type != DataType::Int)
+            {
+                return Err(DobbyError::InvalidReference(
+                    column.clone(),
+                    reference.to_string(),
+                ));
+            }
+            if let Some(rule) = attributes.rules.iter().find(|r| !r.applies_to(*data_type)) {
+                return Err(DobbyError::InvalidRule(
+                    rule.clone(),
+                    column.clone(),
+                    *data_type,
+                ));
+            }
+            if let Some(default) = attributes.default {
+                let default = default.coerce(*data_type)?;
+                default.validate()?;
+                self.attributes
+                    .get_mut(&table)
+                    .unwrap()
+                    .get_mut(column)
+                    .unwrap()
+                    .default = Some(default);
+            }
+        }
+        if let Entry::Occupied(mut entry) = self.tables.entry(table.clone()) {
+            for (column, data_type) in retype {
+                match entry.get_mut().iter_mut().find(|(c, _)| c == &column) {
+                    Some((_, old_type)) => *old_type = data_type,
+                    None => return Err(DobbyError::ColumnNotFound(column, table)),
+                }
+                let attributes = self.attributes.get_mut(&table);
+                if let Some(attributes) = attributes.and_then(|a| a.get_mut(&column)) {
+                    if data_type != DataType::Enum {
+                        attributes.labels.clear();
+                    }
+                    if data_type != DataType::Decimal {
+                        attributes.precision = None;
+                    }
+                    if data_type != DataType::Uuid {
+                        attributes.generate = None;
+                    }
+                }
+            }
+            Ok(())
+        } else {
+            Err(DobbyError::TableNotFound(table))
+        }
+    }
+
+    /// Coerces the values to the datatypes of the table's columns
+    pub fn coerce(&self, table: &str, values: ColumnSet) -> Result<ColumnSet, DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        let mut coerced = HashMap::new();
+        for (column, value) in values {
+            let data_type = columns
+                .iter()
+                .find_map(|(c, data_type)| (c == &column).then_some(*data_type))
+                .ok_or_else(|| DobbyError::ColumnNotFound(column.clone(), table.to_string()))?;
+            let mut value = value.coerce(data_type)?;
+            value.validate()?;
+            match &value {
+                TypedValue::Enum(label)
+                    if !self
+                        .column_attributes(table, &column)
+                        .labels
+                        .contains(label) =>
+                {
+                    return Err(DobbyError::UnknownLabel(label.clone(), column));
+                }
+                // decimals are stored with the column's scale, or without trailing zeros,
+                // so that equal ones are stored the same
+                TypedValue::Decimal(decimal) => {
+                    let fitted = match self.column_attributes(table, &column).precision {
+                        Some(precision) => precision.fit(*decimal).ok_or_else(|| {
+                            DobbyError::PrecisionExceeded(value.clone(), column.clone(), precision)
+                        })?,
+                        None => decimal.normalize(),
+                    };
+                    value = TypedValue::Decimal(fitted);
+                }
+                _ => {}
+            }
+            coerced.insert(column, value);
+        }
+        Ok(coerced)
+    }
+
+    /// Coerces the conditions of a query on the table,
+    /// which can also compare parts of JSON columns
+    pub fn coerce_conditions(
+        &self,
+        table: &str,
+        conditions: ColumnSet,
+    ) -> Result<ColumnSet, DobbyError> {
+        let (paths, columns): (ColumnSet, ColumnSet) = conditions
+            .into_iter()
+            .partition(|(key, _)| split_json_path(key).is_some());
+        let mut coerced = self.coerce(table, columns)?;
+        for (key, value) in paths {
+            let (column, path) = split_json_path(&key).unwrap();
+            let data_type = self.tables[table]
+                .iter()
+                .find_map(|(name, data_type)| (name == column).then_some(*data_type));
+            match data_type {
+                Some(DataType::Json) => {}
+                Some(data_type) => return Err(DobbyError::InvalidValue(value, data_type)),
+                None => return Err(DobbyError::ColumnNotFound(key, table.to_string())),
+            }
+            json_path_segments(path).ok_or_else(|| DobbyError::InvalidName(key.clone()))?;
+            coerced.insert(key, value.coerce(DataType::Json)?);
+        }
+        Ok(coerced)
+    }
+
+    /// Checks that the table has all of the columns
+    pub fn check_columns(&self, table: &str, columns: &[String]) -> Result<(), DobbyError> {
+        let known = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        match columns
+            .iter()
+            .find(|c| !known.iter().any(|(name, _)| name == *c))
+        {
+            Some(column) => Err(DobbyError::ColumnNotFound(
+                column.clone(),
+                table.to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The attributes of the column, or the default ones if it has none
+    pub fn column_attributes(&self, table: &str, column: &str) -> Attributes {
+        self.attributes
+            .get(table)
+            .and_then(|attributes| attributes.get(column))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Sets the labels of the table's enum columns, which every one of them needs
+    pub fn set_labels(
+        &mut self,
+        table: &str,
+        mut labels: HashMap<String, Vec<String>>,
+    ) -> Result<(), DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?
+            .clone();
+        if let Some(column) = labels
+            .keys()
+            .find(|l| !columns.iter().any(|(c, _)| c == *l))
+        {
+            return Err(DobbyError::ColumnNotFound(
+                column.clone(),
+                table.to_string(),
+            ));
+        }
+        for (column, data_type) in columns {
+            let column_labels = labels.remove(&column);
+            match (data_type, column_labels) {
+                (DataType::Enum, Some(column_labels)) if valid_labels(&column_labels) => {
+                    self.attributes
+                        .entry(table.to_string())
+                        .or_default()
+                        .entry(column)
+                        .or_default()
+                        .labels = column_labels;
+                }
+                (DataType::Enum, _) | (_, Some(_)) => {
+                    return Err(DobbyError::InvalidLabels(column));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The labels of the table's enum columns
+    pub fn labels(&self, table: &str) -> HashMap<String, Vec<String>> {
+        self.attributes
+            .get(table)
+            .into_iter()
+            .flatten()
+            .filter(|(_, attributes)| !attributes.labels.is_empty())
+            .map(|(column, attributes)| (column.clone(), attributes.labels.clone()))
+            .collect()
+    }
+
+    /// Sets the precision of the table's decimal columns, which is optional
+    pub fn set_precisions(
+        &mut self,
+        table: &str,
+        precisions: HashMap<String, Precision>,
+    ) -> Result<(), DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        for (column, precision) in precisions {
+            let data_type = columns
+                .iter()
+                .find_map(|(c, data_type)| (c == &column).then_some(*data_type))
+                .ok_or_else(|| DobbyError::ColumnNotFound(column.clone(), table.to_string()))?;
+            if data_type != DataType::Decimal || !precision.is_valid() {
+                return Err(DobbyError::InvalidPrecision(column));
+            }
+            self.attributes
+                .entry(table.to_string())
+                .or_default()
+                .entry(column)
+                .or_default()
+                .precision = Some(precision);
+        }
+        Ok(())
+    }
+
+    /// Makes the uuid columns get a new uuid when omitted on insert
+    pub fn set_generated(
+        &mut self,
+        table: &str,
+        generated: HashMap<String, UuidVersion>,
+    ) -> Result<(), DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        for (column, version) in generated {
+            let data_type = columns
+                .iter()
+                .find_map(|(c, data_type)| (c == &column).then_some(*data_type))
+                .ok_or_else(|| DobbyError::ColumnNotFound(column.clone(), table.to_string()))?;
+            if data_type != DataType::Uuid {
+                return Err(DobbyError::InvalidGenerated(column, table.to_string()));
+            }
+            self.attributes
+                .entry(table.to_string())
+                .or_default()
+                .entry(column)
+                .or_default()
+                .generate = Some(version);
+        }
+        Ok(())
+    }
+
+    /// Sets the values to insert when the columns are omitted
+    pub fn set_defaults(&mut self, table: &str, defaults: ColumnSet) -> Result<(), DobbyError> {
+        let defaults = self.coerce(table, defaults)?;
+        self.check_rules(table, &defaults)?;
+        for (column, default) in defaults {
+            self.attributes
+                .entry(table.to_string())
+                .or_default()
+                .entry(column)
+                .or_default()
+                .default = Some(default);
+        }
+        Ok(())
+    }
+
+    /// Sets the rules the values of the columns have to meet
+    pub fn set_rules(
+        &mut self,
+        table: &str,
+        rules: HashMap<String, Vec<Rule>>,
+    ) -> Result<(), DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        for (column, column_rules) in rules {
+            let data_type = columns
+                .iter()
+                .find_map(|(c, data_type)| (c == &column).then_some(*data_type))
+                .ok_or_else(|| DobbyError::ColumnNotFound(column.clone(), table.to_string()))?;
+            if let Some(rule) = column_rules.iter().find(|r| !r.applies_to(data_type)) {
+                return Err(DobbyError::InvalidRule(rule.clone(), column, data_type));
+            }
+            self.attributes
+                .entry(table.to_string())
+                .or_default()
+                .entry(column)
+                .or_default()
+                .rules = column_rules;
+        }
+        Ok(())
+    }
+
+    /// Checks the coerced values against the rules of their columns
+    pub fn check_rules(&self, table: &str, values: &ColumnSet) -> Result<(), DobbyError> {
+        let Some(attributes) = self.attributes.get(table) else {
+            return Ok(());
+        };
+        for (column, value) in values {
+            let rules = attributes.get(column).map(|a| a.rules.as_slice());
+            if let Some(rule) = rules.unwrap_or_default().iter().find(|r| !r.check(value)) {
+                return Err(DobbyError::RuleViolated(
+                    value.clone(),
+                    column.clone(),
+                    rule.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the columns reference the key columns of other tables, which are
+    /// their auto-increment columns
+    pub fn set_references(
+        &mut self,
+        table: &str,
+        references: HashMap<String, Reference>,
+    ) -> Result<(), DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        for (column, reference) in references {
+            let data_type = columns
+                .iter()
+                .find_map(|(c, data_type)| (c == &column).then_some(*data_type))
+                .ok_or_else(|| DobbyError::ColumnNotFound(column.clone(), table.to_string()))?;
+            let key = self.auto_increment(&reference.table).map(|(key, _)| key);
+            if data_type != DataType::Int || key.as_ref() != Some(&reference.column) {
+                return Err(DobbyError::InvalidReference(column, reference.to_string()));
+            }
+            self.attributes
+                .entry(table.to_string())
+                .or_default()
+                .entry(column)
+                .or_default()
+                .references = Some(reference);
+        }
+        Ok(())
+    }
+
+    /// The columns that reference the table, as (table, column, reference)
+    pub fn references_to(&self, table: &str) -> Vec<(String, String, Reference)> {
+        let mut references = Vec::new();
+        for (child, attributes) in &self.attributes {
+            for (column, attributes) in attributes {
+                match &attributes.references {
+                    Some(reference) if reference.table == table => {
+                        references.push((child.clone(), column.clone(), reference.clone()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        references
+    }
+
+    /// Points the references to a renamed table or its renamed columns to the new names
+    fn retarget_references(&mut self, table: &str, to: &str, rename: &HashMap<String, String>) {
+        let trashed = self
+            .trash
+            .values_mut()
+            .map(|dropped| &mut dropped.attributes);
+        for attributes in self.attributes.values_mut().chain(trashed) {
+            for reference in attributes
+                .values_mut()
+                .filter_map(|a| a.references.as_mut())
+            {
+                if reference.table == table {
+                    reference.table = to.to_string();
+                    if let Some(column) = rename.get(&reference.column) {
+                        reference.column = column.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fills in the default values of the columns missing from `values`
+    /// Checks that the rows the table already has get values for the added columns,
+    /// from their defaults or generators
+    pub fn check_added_columns(
+        &self,
+        table: &str,
+        added: &[(String, DataType)],
+    ) -> Result<(), DobbyError> {
+        for (column, _) in added {
+            let attributes = self.column_attributes(table, column);
+            if attributes.default.is_none() && attributes.generate.is_none() {
+                return Err(DobbyError::IncompleteData(
+                    column.clone(),
+                    table.to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fill_defaults(&self, table: &str, values: &mut ColumnSet) {
+        let Some(attributes) = self.attributes.get(table) else {
+            return;
+        };
+        for (column, attributes) in attributes {
+            if let Some(default) = &attributes.default {
+                values
+                    .entry(column.clone())
+                    .or_insert_with(|| default.clone());
+            }
+            if let Some(version) = attributes.generate {
+                values
+                    .entry(column.clone())
+                    .or_insert_with(|| TypedValue::Uuid(version.generate()));
+            }
+        }
+    }
+
+    /// Makes the column auto-increment, starting from 1.
+    /// Only int columns can auto-increment, and only one per table.
+    pub fn set_auto_increment(&mut self, table: &str, column: &str) -> Result<(), DobbyError> {
+        let columns = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DobbyError::TableNotFound(table.to_string()))?;
+        match columns.iter().find(|(c, _)| c == column) {
+            Some((_, DataType::Int)) => {}
+            Some(_) => {
+                return Err(DobbyError::InvalidAutoIncrement(
+                    column.to_string(),
+                    table.to_string(),
+                ))
+            }
+            None => {
+                return Err(DobbyError::ColumnNotFound(
+                    column.to_string(),
+                    table.to_string(),
+                ))
+            }
+        }
+        if self.auto_increment(table).is_some() {
+            return Err(DobbyError::InvalidAutoIncrement(
+                column.to_string(),
+                table.to_string(),
+            ));
+        }
+        self.attributes
+            .entry(table.to_string())
+            .or_default()
+            .entry(column.to_string())
+            .or_default()
+            .auto_increment = Some(1);
+        Ok(())
+    }
+
+    /// The table's auto-increment column along with its next value
+    pub fn auto_increment(&self, table: &str) -> Option<(String, i64)> {
+        self.attributes
+            .get(table)?
+            .iter()
+            .find_map(|(column, attributes)| {
+                attributes.auto_increment.map(|next| (column.clone(), next))
+            })
+    }
+
+    /// Checks that the auto-increment counter could move past the value the row gives
+    /// the column, or the one it would be given, since the largest int has no next one
+    pub fn check_auto_increment(&self, table: &str, values: &ColumnSet) -> Result<(), DobbyError> {
+        let Some((column, next)) = self.auto_increment(table) else {
+            return Ok(());
+        };
+        let value = match values.get(&column) {
+            Some(TypedValue::Int(value)) => *value,
+            Some(_) => return Ok(()),
+            None => next,
+        };
+        if value == i64::MAX {
+            return Err(DobbyError::AutoIncrementExhausted(
+                column,
+                table.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves the auto-increment counter past the value used by the inserted row,
+    /// which `check_auto_increment` keeps below the largest int
+    pub fn advance_auto_increment(&mut self, table: &str, row: &ColumnSet) {
+        let Some((column, next)) = self.auto_increment(table) else {
+            return;
+        };
+        if let Some(TypedValue::Int(used)) = row.get(&column) {
+            if *used >= next {
+                self.attributes
+                    .get_mut(table)
+                    .unwrap()
+                    .get_mut(&column)
+                    .unwrap()
+                    .auto_increment = Some(used.saturating_add(1));
+            }
+        }
+    }
+
+    pub fn validate_name(name: &str) -> Result<(), DobbyError> {
+        if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
             Ok(())
         } else {
             Err(DobbyError::InvalidName(name.to_string()))
         }
     }
+
+    /// Validates the name of a column, which can't be one of the `RESERVED_COLUMNS` either
+    pub fn validate_column_name(name: &str) -> Result<(), DobbyError> {
+        Self::validate_name(name)?;
+        if RESERVED_COLUMNS.contains(&name) {
+            return Err(DobbyError::InvalidName(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Labels fit in a byte and can be told apart
+fn valid_labels(labels: &[String]) -> bool {
+    let distinct: std::collections::HashSet<_> = labels.iter().collect();
+    !labels.is_empty()
+        && labels.len() <= 256
+        && distinct.len() == labels.len()
+        && labels.iter().all(|label| !label.is_empty())
 }
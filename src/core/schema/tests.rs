@@ -1,9 +1,12 @@
 use super::*;
+use crate::core::types::{OnDelete, Precision, UuidVersion};
 
 #[test]
 fn create() -> Result<(), DobbyError> {
     let mut schema = Schema {
         tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
         name: "".into(),
         kind: SchemaKind::Dobby,
     };
@@ -20,15 +23,22 @@ fn create() -> Result<(), DobbyError> {
 fn drop() -> Result<(), DobbyError> {
     let mut schema = Schema {
         tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
         name: "".into(),
         kind: SchemaKind::Dobby,
     };
     let table_schema = vec![("column".into(), DataType::String)];
 
     schema.create_table("test_table".to_string(), table_schema)?;
-    schema.drop_table("test_table".to_string())?;
+    schema.trash_table("test_table".to_string(), 0)?;
 
     assert_eq!(schema.tables.len(), 0);
+    assert!(schema.trash.contains_key("test_table"));
+    assert!(matches!(
+        schema.trash_table("test_table".to_string(), 0),
+        Err(DobbyError::TableInTrash(_))
+    ));
     Ok(())
 }
 
@@ -36,6 +46,8 @@ fn drop() -> Result<(), DobbyError> {
 fn alter() -> Result<(), DobbyError> {
     let mut schema = Schema {
         tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
         name: "".into(),
         kind: SchemaKind::Dobby,
     };
@@ -54,3 +66,506 @@ fn alter() -> Result<(), DobbyError> {
     );
     Ok(())
 }
+
+#[test]
+fn add_and_drop_columns() -> Result<(), DobbyError> {
+    let mut schema = Schema {
+        tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
+        name: "".into(),
+        kind: SchemaKind::Dobby,
+    };
+    let table_schema = vec![("column".into(), DataType::String)];
+
+    schema.create_table("test_table".to_string(), table_schema)?;
+    schema.add_columns(
+        "test_table".to_string(),
+        vec![("added".into(), DataType::Int)],
+        [("added".into(), 5.into())].into(),
+        HashMap::new(),
+        HashMap::new(),
+        HashMap::new(),
+    )?;
+    // the default is kept for later inserts
+    assert_eq!(
+        schema.column_attributes("test_table", "added").default,
+        Some(5.into())
+    );
+
+    // attributes can only be given to the added columns
+    assert!(matches!(
+        schema.add_columns(
+            "test_table".to_string(),
+            vec![("other".into(), DataType::Int)],
+            [("column".into(), "a".into())].into(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ),
+        Err(DobbyError::ColumnNotFound(column, _)) if column == "column"
+    ));
+    assert!(matches!(
+        schema.add_columns(
+            "test_table".to_string(),
+            vec![("kind".into(), DataType::Enum)],
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ),
+        Err(DobbyError::InvalidLabels(_))
+    ));
+    schema.add_columns(
+        "test_table".to_string(),
+        vec![("kind".into(), DataType::Enum)],
+        [("kind".into(), "b".into())].into(),
+        [("kind".into(), vec!["a".into(), "b".into()])].into(),
+        HashMap::new(),
+        HashMap::new(),
+    )?;
+    assert_eq!(
+        schema.column_attributes("test_table", "kind").default,
+        Some(TypedValue::Enum("b".into()))
+    );
+    schema.drop_columns("test_table".to_string(), vec!["kind".into()])?;
+    schema.drop_columns("test_table".to_string(), vec!["column".into()])?;
+
+    assert_eq!(
+        schema.tables["test_table"],
+        vec![("added".into(), DataType::Int)]
+    );
+    assert!(matches!(
+        schema.drop_columns("test_table".to_string(), vec!["added".into()]),
+        Err(DobbyError::NoColumns)
+    ));
+    Ok(())
+}
+
+#[test]
+fn retype_columns() -> Result<(), DobbyError> {
+    let mut schema = Schema {
+        tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
+        name: "".into(),
+        kind: SchemaKind::Dobby,
+    };
+    let table_schema = vec![("column".into(), DataType::Int)];
+
+    schema.create_table("test_table".to_string(), table_schema)?;
+    schema.retype_columns(
+        "test_table".to_string(),
+        [("column".into(), DataType::Float)].into(),
+    )?;
+
+    assert_eq!(
+        schema.tables["test_table"],
+        vec![("column".into(), DataType::Float)]
+    );
+    assert!(matches!(
+        schema.retype_columns(
+            "test_table".to_string(),
+            [("other".into(), DataType::Float)].into()
+        ),
+        Err(DobbyError::ColumnNotFound(_, _))
+    ));
+    Ok(())
+}
+
+#[test]
+fn rename() -> Result<(), DobbyError> {
+    let mut schema = Schema {
+        tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
+        name: "".into(),
+        kind: SchemaKind::Dobby,
+    };
+    let table_schema = vec![("column".into(), DataType::String)];
+
+    schema.create_table("test_table".to_string(), table_schema.clone())?;
+    schema.create_table("other_table".to_string(), table_schema.clone())?;
+    schema.rename_table("test_table".to_string(), "renamed".to_string())?;
+
+    assert_eq!(schema.tables.len(), 2);
+    assert_eq!(schema.tables["renamed"], table_schema);
+    assert!(matches!(
+        schema.rename_table("renamed".to_string(), "other_table".to_string()),
+        Err(DobbyError::TableAlreadyExists(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn reserved_names() -> Result<(), DobbyError> {
+    let mut schema = Schema {
+        tables: HashMap::new(),
+        attributes: HashMap::new(),
+        trash: HashMap::new(),
+        name: "".into(),
+        kind: SchemaKind::Dobby,
+    };
+    let table_schema = vec![("column".into(), DataType::String)];
+
+    assert!(matches!(
+        schema.create_table(
+            "test_table".to_string(),
+            vec![("all".into(), DataType::Bool)]
+        ),
+        Err(DobbyError::InvalidName(_))
+    ));
+    // a table can still be named like that
+    schema.create_table("all".to_string(), table_schema)?;
+    assert!(matches!(
+        schema.alter_table("all".to_string(), [("column".into(), "all".into())].into()),
+        Err(DobbyError::InvalidName(_))
+    ));
+    assert!(matches!(
+        schema.add_columns(
+            "all".to_string(),
+            vec![("all".into(), DataType::Bool)],
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ),
+        Err(DobbyError::InvalidName(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn trash() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![("column".into(), DataType::String)];
+
+    schema.create_table("test_table".to_string(), table_schema.clone())?;
+    schema.trash_table("test_table".to_string(), 100)?;
+    assert!(schema.tables.is_empty());
+
+    let retention = Duration::from_secs(10);
+    assert!(schema.expired_tables(retention, 109).is_empty());
+    assert_eq!(schema.expired_tables(retention, 110), vec!["test_table"]);
+
+    schema.restore_table("test_table".to_string())?;
+    assert_eq!(schema.tables["test_table"], table_schema);
+    assert!(schema.trash.is_empty());
+    Ok(())
+}
+
+#[test]
+fn auto_increment() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("id".into(), DataType::Int),
+        ("name".into(), DataType::String),
+    ];
+
+    schema.create_table("test_table".to_string(), table_schema)?;
+    assert!(matches!(
+        schema.set_auto_increment("test_table", "name"),
+        Err(DobbyError::InvalidAutoIncrement(_, _))
+    ));
+    schema.set_auto_increment("test_table", "id")?;
+    assert_eq!(schema.auto_increment("test_table"), Some(("id".into(), 1)));
+
+    schema.advance_auto_increment("test_table", &[("id".into(), 10.into())].into());
+    schema.advance_auto_increment("test_table", &[("id".into(), 5.into())].into());
+    assert_eq!(schema.auto_increment("test_table"), Some(("id".into(), 11)));
+
+    schema.alter_table("test_table".into(), [("id".into(), "key".into())].into())?;
+    schema.rename_table("test_table".into(), "renamed".into())?;
+    assert_eq!(schema.auto_increment("renamed"), Some(("key".into(), 11)));
+    assert!(matches!(
+        schema.retype_columns("renamed".into(), [("key".into(), DataType::Float)].into()),
+        Err(DobbyError::InvalidAutoIncrement(_, _))
+    ));
+
+    schema.trash_table("renamed".into(), 0)?;
+    schema.restore_table("renamed".into())?;
+    assert_eq!(schema.auto_increment("renamed"), Some(("key".into(), 11)));
+
+    // the largest int has no next value, given or generated
+    let exhausted = |result| matches!(result, Err(DobbyError::AutoIncrementExhausted(_, _)));
+    let largest = [("key".into(), i64::MAX.into())].into();
+    assert!(exhausted(schema.check_auto_increment("renamed", &largest)));
+    let below = [("key".into(), (i64::MAX - 1).into())].into();
+    schema.check_auto_increment("renamed", &below)?;
+    schema.advance_auto_increment("renamed", &below);
+    assert!(exhausted(
+        schema.check_auto_increment("renamed", &HashMap::new())
+    ));
+    Ok(())
+}
+
+#[test]
+fn defaults() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("id".into(), DataType::Int),
+        ("name".into(), DataType::String),
+    ];
+
+    schema.create_table("test_table".to_string(), table_schema)?;
+    assert!(matches!(
+        schema.set_defaults("test_table", [("id".into(), "one".into())].into()),
+        Err(DobbyError::InvalidValue(_, _))
+    ));
+    schema.set_defaults("test_table", [("id".into(), "1".into())].into())?;
+
+    let mut values = [("name".into(), "name".into())].into();
+    schema.fill_defaults("test_table", &mut values);
+    assert_eq!(values["id"], TypedValue::Int(1));
+
+    schema.retype_columns("test_table".into(), [("id".into(), DataType::Float)].into())?;
+    let attributes = schema.column_attributes("test_table", "id");
+    assert_eq!(attributes.default, Some(TypedValue::Float(1.0)));
+    Ok(())
+}
+
+#[test]
+fn rules() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("price".into(), DataType::Int),
+        ("model".into(), DataType::String),
+        ("letters".into(), DataType::CharInvl),
+        ("period".into(), DataType::TimestampInvl),
+    ];
+    schema.create_table("test_table".to_string(), table_schema)?;
+
+    assert!(matches!(
+        schema.set_rules(
+            "test_table",
+            [("price".into(), vec![Rule::MaxLength(3)])].into()
+        ),
+        Err(DobbyError::InvalidRule(_, _, DataType::Int))
+    ));
+    assert!(matches!(
+        schema.set_rules(
+            "test_table",
+            [("model".into(), vec![Rule::Pattern("(".into())])].into()
+        ),
+        Err(DobbyError::InvalidRule(_, _, DataType::String))
+    ));
+    let rules = [
+        ("price".into(), vec![Rule::Min(0.0), Rule::Max(100.0)]),
+        (
+            "model".into(),
+            vec![Rule::MaxLength(5), Rule::Pattern("^[A-Z]".into())],
+        ),
+        ("letters".into(), vec![Rule::MaxWidth(2.0)]),
+        ("period".into(), vec![Rule::MaxWidth(3600.0)]),
+    ];
+    schema.set_rules("test_table", rules.into())?;
+
+    let valid = [
+        ("price".into(), 100.into()),
+        ("model".into(), "Fiat".into()),
+        ("letters".into(), TypedValue::CharInvl('a', 'c')),
+        (
+            "period".into(),
+            TypedValue::TimestampInvl(
+                chrono::DateTime::UNIX_EPOCH,
+                chrono::DateTime::UNIX_EPOCH + chrono::Duration::hours(1),
+            ),
+        ),
+    ];
+    schema.check_rules("test_table", &valid.into())?;
+
+    for (column, value, rule) in [
+        ("price", TypedValue::Int(-1), Rule::Min(0.0)),
+        ("model", "Ferrari".into(), Rule::MaxLength(5)),
+        ("model", "fiat".into(), Rule::Pattern("^[A-Z]".into())),
+        (
+            "letters",
+            TypedValue::CharInvl('a', 'd'),
+            Rule::MaxWidth(2.0),
+        ),
+        // too wide to count in microseconds
+        (
+            "period",
+            TypedValue::TimestampInvl(
+                chrono::DateTime::<chrono::Utc>::MIN_UTC,
+                chrono::DateTime::<chrono::Utc>::MAX_UTC,
+            ),
+            Rule::MaxWidth(3600.0),
+        ),
+    ] {
+        match schema.check_rules("test_table", &[(column.into(), value)].into()) {
+            Err(DobbyError::RuleViolated(_, c, r)) => assert_eq!((c.as_str(), r), (column, rule)),
+            result => panic!("{} should break {}, got {:?}", column, rule, result),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn references() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("id".into(), DataType::Int),
+        ("owner".into(), DataType::Int),
+    ];
+    schema.create_table("users".to_string(), vec![("id".into(), DataType::Int)])?;
+    schema.create_table("cars".to_string(), table_schema)?;
+    let reference = Reference {
+        table: "users".into(),
+        column: "id".into(),
+        on_delete: OnDelete::Cascade,
+    };
+
+    // only auto-increment columns are keys
+    assert!(matches!(
+        schema.set_references("cars", [("owner".into(), reference.clone())].into()),
+        Err(DobbyError::InvalidReference(_, _))
+    ));
+    schema.set_auto_increment("users", "id")?;
+    schema.set_references("cars", [("owner".into(), reference.clone())].into())?;
+    assert_eq!(
+        schema.references_to("users"),
+        vec![("cars".into(), "owner".into(), reference)]
+    );
+
+    assert!(matches!(
+        schema.trash_table("users".into(), 0),
+        Err(DobbyError::Referenced(_, _))
+    ));
+    assert!(matches!(
+        schema.drop_columns("users".into(), vec!["id".into()]),
+        Err(DobbyError::Referenced(_, _))
+    ));
+
+    schema.alter_table("users".into(), [("id".into(), "key".into())].into())?;
+    schema.rename_table("users".into(), "people".into())?;
+    let reference = schema
+        .column_attributes("cars", "owner")
+        .references
+        .unwrap();
+    assert_eq!(reference.to_string(), "people.key");
+    Ok(())
+}
+
+#[test]
+fn labels() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("id".into(), DataType::Int),
+        ("status".into(), DataType::Enum),
+    ];
+    schema.create_table("test_table".to_string(), table_schema)?;
+
+    let labels = |list: &[&str]| -> HashMap<String, Vec<String>> {
+        [(
+            "status".into(),
+            list.iter().map(|s| s.to_string()).collect(),
+        )]
+        .into()
+    };
+    assert!(matches!(
+        schema.set_labels("test_table", labels(&[])),
+        Err(DobbyError::InvalidLabels(_))
+    ));
+    assert!(matches!(
+        schema.set_labels("test_table", labels(&["open", "open"])),
+        Err(DobbyError::InvalidLabels(_))
+    ));
+    assert!(matches!(
+        schema.set_labels("test_table", [("id".into(), vec!["one".into()])].into()),
+        Err(DobbyError::InvalidLabels(_))
+    ));
+    schema.set_labels("test_table", labels(&["open", "closed"]))?;
+    assert_eq!(schema.labels("test_table"), labels(&["open", "closed"]));
+
+    let values = schema.coerce("test_table", [("status".into(), "open".into())].into())?;
+    assert_eq!(values["status"], TypedValue::Enum("open".into()));
+    assert!(matches!(
+        schema.coerce("test_table", [("status".into(), "lost".into())].into()),
+        Err(DobbyError::UnknownLabel(_, _))
+    ));
+
+    schema.retype_columns(
+        "test_table".into(),
+        [("status".into(), DataType::String)].into(),
+    )?;
+    assert!(schema.labels("test_table").is_empty());
+    Ok(())
+}
+
+#[test]
+fn precisions() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("price".into(), DataType::Decimal),
+        ("amount".into(), DataType::Decimal),
+    ];
+    schema.create_table("test_table".to_string(), table_schema)?;
+
+    for precision in [(0, 0), (29, 2), (4, 5)] {
+        let (precision, scale) = precision;
+        assert!(matches!(
+            schema.set_precisions(
+                "test_table",
+                [("price".into(), Precision { precision, scale })].into()
+            ),
+            Err(DobbyError::InvalidPrecision(_))
+        ));
+    }
+    schema.set_precisions(
+        "test_table",
+        [("price".into(), Precision { precision: 5, scale: 2 })].into(),
+    )?;
+
+    let decimal = |value: TypedValue| -> Result<String, DobbyError> {
+        let values = schema.coerce("test_table", [("price".into(), value)].into())?;
+        Ok(values["price"].to_string())
+    };
+    assert_eq!(decimal("1.5".into())?, "1.50");
+    assert_eq!(decimal(TypedValue::Int(123))?, "123.00");
+    assert_eq!(decimal("-999.990".into())?, "-999.99");
+    for value in ["1.234", "1000"] {
+        assert!(matches!(
+            decimal(value.into()),
+            Err(DobbyError::PrecisionExceeded(_, _, _))
+        ));
+    }
+
+    // decimals without a precision lose their trailing zeros
+    let values = schema.coerce("test_table", [("amount".into(), "0.10".into())].into())?;
+    assert_eq!(values["amount"].to_string(), "0.1");
+    Ok(())
+}
+
+#[test]
+fn generated() -> Result<(), DobbyError> {
+    let mut schema = Schema::new_dobby("".into());
+    let table_schema = vec![
+        ("id".into(), DataType::Uuid),
+        ("name".into(), DataType::String),
+    ];
+    schema.create_table("test_table".to_string(), table_schema)?;
+    assert!(matches!(
+        schema.set_generated("test_table", [("name".into(), UuidVersion::V4)].into()),
+        Err(DobbyError::InvalidGenerated(_, _))
+    ));
+    schema.set_generated("test_table", [("id".into(), UuidVersion::V7)].into())?;
+
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let mut values = [("name".into(), "name".into())].into();
+        schema.fill_defaults("test_table", &mut values);
+        match values.remove("id") {
+            Some(TypedValue::Uuid(id)) => ids.push(id),
+            id => panic!("Expected a uuid, got {:?}", id),
+        }
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    // time-ordered, to the millisecond
+    assert!(ids[0] < ids[1]);
+
+    let mut values = [("id".into(), "not-generated".into())].into();
+    schema.fill_defaults("test_table", &mut values);
+    assert_eq!(values["id"], "not-generated".into());
+    Ok(())
+}
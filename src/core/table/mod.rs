@@ -1,4 +1,7 @@
-use super::types::{ColumnSet, DataType, DobbyError, TypedValue};
+use super::types::{
+    json_lookup, json_path_segments, skip_bytes, split_json_path, ColumnSet, DataType, DobbyError,
+    TypedValue,
+};
 
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
@@ -8,10 +11,18 @@ use std::path::Path;
 #[cfg(test)]
 mod tests;
 
+/// Marks stored in the first byte of every record
+const LIVE: u8 = 0;
+const DELETED: u8 = 1;
+/// The row was replaced by its updated version, so it can't be restored
+const UPDATED: u8 = 2;
+
 #[derive(Debug)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<(String, DataType)>,
+    /// Labels of the enum columns, whose values are stored as label indices
+    pub labels: HashMap<String, Vec<String>>,
     pub file: File,
 }
 
@@ -21,47 +32,125 @@ struct Row {
     offset: u64,
 }
 
+/// Lazy iterator over the rows of a table that match the conditions,
+/// reading them from the file one by one
+pub struct Rows<'a> {
+    table: &'a mut Table,
+    columns: Vec<String>,
+    conditions: ColumnSet,
+}
+
+impl Rows<'_> {
+    /// The offset into the file right after the last row read
+    pub fn offset(&mut self) -> Result<u64, DobbyError> {
+        self.table
+            .file
+            .stream_position()
+            .map_err(DobbyError::IoError)
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<ColumnSet, DobbyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // bytes that are neither projected nor filtered on aren't loaded
+        let needed = |column: &str| {
+            self.columns.is_empty()
+                || self.columns.iter().any(|c| c == column)
+                || self.conditions.contains_key(column)
+        };
+        loop {
+            let mut row = match self.table.next_live(&needed)? {
+                Ok(Row { row, .. }) => row,
+                Err(e) => return Some(Err(DobbyError::IoError(e))),
+            };
+
+            match self.table.check_conditions(&row, &self.conditions) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+
+            row.retain(|key, _| self.columns.is_empty() || self.columns.contains(key));
+            return Some(Ok(row));
+        }
+    }
+}
+
 // TODO: add cleanup (remove all deleted entries)
 impl Table {
-    fn next_row(&mut self) -> Option<Result<Row, io::Error>> {
+    /// Reads the next record along with its mark, whether it's deleted or not
+    fn next_record(&mut self) -> Option<Result<(u8, Row), io::Error>> {
+        self.read_record(&|_| true)
+    }
+
+    /// Reads the next record, skipping the bytes columns that aren't `needed`
+    fn read_record(
+        &mut self,
+        needed: &dyn Fn(&str) -> bool,
+    ) -> Option<Result<(u8, Row), io::Error>> {
         let mut row = HashMap::new();
-        let mut deleted = [0];
-        let mut offset;
-        loop {
-            offset = self.file.stream_position().unwrap();
-            self.file.read_exact(&mut deleted).ok()?;
+        let mut mark = [0];
+        let offset = self.file.stream_position().unwrap();
+        self.file.read_exact(&mut mark).ok()?;
 
-            for (column, data_type) in &self.columns {
-                match TypedValue::read(*data_type, &mut self.file) {
-                    Ok(value) => row.insert(column.clone(), value),
-                    Err(e) => return Some(Err(e)),
-                };
+        for (column, data_type) in &self.columns {
+            if *data_type == DataType::Bytes && !needed(column) {
+                if let Err(e) = skip_bytes(&mut self.file) {
+                    return Some(Err(e));
+                }
+                continue;
             }
+            let value = match data_type {
+                DataType::Enum => read_label(&mut self.file, self.labels.get(column)),
+                _ => TypedValue::read(*data_type, &mut self.file),
+            };
+            match value {
+                Ok(value) => row.insert(column.clone(), value),
+                Err(e) => return Some(Err(e)),
+            };
+        }
 
-            if deleted[0] == 0 {
-                break;
+        Some(Ok((mark[0], Row { offset, row })))
+    }
+
+    fn next_row(&mut self) -> Option<Result<Row, io::Error>> {
+        self.next_live(&|_| true)
+    }
+
+    fn next_live(&mut self, needed: &dyn Fn(&str) -> bool) -> Option<Result<Row, io::Error>> {
+        loop {
+            match self.read_record(needed)? {
+                Ok((LIVE, row)) => return Some(Ok(row)),
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e)),
             }
         }
-
-        Some(Ok(Row { offset, row }))
     }
 
-    fn delete_at(&mut self, offset: u64) -> Result<(), io::Error> {
+    fn mark_at(&mut self, offset: u64, mark: u8) -> Result<(), io::Error> {
         self.file.seek(SeekFrom::Start(offset))?;
-        self.file.write_all(&[1])?;
+        self.file.write_all(&[mark])?;
         self.file.seek(SeekFrom::Current(-1))?;
         Ok(())
     }
 
-    pub fn open(name: String, columns: Vec<(String, DataType)>, path: &Path) -> Self {
+    pub fn open(
+        name: String,
+        columns: Vec<(String, DataType)>,
+        labels: HashMap<String, Vec<String>>,
+        path: &Path,
+    ) -> Self {
         log::info!("Opening table `{}`", name);
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path.join(name.clone()))
             .expect("Failed to open table");
-        Self { name, columns, file }
+        Self { name, columns, labels, file }
     }
 
     fn coerce(&self, mut column_set: ColumnSet) -> Result<ColumnSet, DobbyError> {
@@ -70,6 +159,11 @@ impl Table {
             if let Some((column, value)) = column_set.remove_entry(column) {
                 let value = value.coerce(*data_type)?;
                 value.validate()?;
+                if let TypedValue::Enum(label) = &value {
+                    if !self.labels.get(&column).is_some_and(|l| l.contains(label)) {
+                        return Err(DobbyError::UnknownLabel(label.clone(), column));
+                    }
+                }
                 coerced.insert(column, value);
             }
         }
@@ -83,6 +177,25 @@ impl Table {
         }
     }
 
+    /// Coerces the conditions, which can also compare parts of JSON columns
+    fn coerce_conditions(&self, conditions: ColumnSet) -> Result<ColumnSet, DobbyError> {
+        let (paths, columns): (ColumnSet, ColumnSet) = conditions
+            .into_iter()
+            .partition(|(key, _)| split_json_path(key).is_some());
+        let mut coerced = self.coerce(columns)?;
+        for (key, value) in paths {
+            let (column, path) = split_json_path(&key).unwrap();
+            match self.columns.iter().find(|(name, _)| name == column) {
+                Some((_, DataType::Json)) => {}
+                Some((_, data_type)) => return Err(DobbyError::InvalidValue(value, *data_type)),
+                None => return Err(DobbyError::ColumnNotFound(key, self.name.clone())),
+            }
+            json_path_segments(path).ok_or_else(|| DobbyError::InvalidName(key.clone()))?;
+            coerced.insert(key, value.coerce(DataType::Json)?);
+        }
+        Ok(coerced)
+    }
+
     fn check_conditions(
         &self,
         row: &ColumnSet,
@@ -92,6 +205,13 @@ impl Table {
         for (column, value) in conditions {
             if let Some(row_value) = row.get(column) {
                 result &= row_value == value;
+            } else if let Some((column, path)) = split_json_path(column) {
+                result &= match (row.get(column), value) {
+                    (Some(TypedValue::Json(document)), TypedValue::Json(value)) => {
+                        json_lookup(document, path) == Some(value)
+                    }
+                    _ => false,
+                };
             } else {
                 return Err(DobbyError::ColumnNotFound(
                     column.clone(),
@@ -102,15 +222,35 @@ impl Table {
         Ok(result)
     }
 
-    pub fn insert(&mut self, values: ColumnSet) -> Result<ColumnSet, DobbyError> {
-        let values = self.coerce(values)?;
-        let mut row = vec![0]; // 0 - "not deleted"
-        for (name, _type) in &self.columns {
+    fn encode(
+        &self,
+        columns: &[(String, DataType)],
+        labels: &HashMap<String, Vec<String>>,
+        values: &ColumnSet,
+    ) -> Result<Vec<u8>, DobbyError> {
+        let mut bytes = Vec::new();
+        for (name, _type) in columns {
             let value = values
                 .get(name)
                 .ok_or_else(|| DobbyError::IncompleteData(name.clone(), self.name.clone()))?;
-            row.extend_from_slice(&value.clone().into_bytes());
+            match value {
+                TypedValue::Enum(label) => {
+                    let index = labels
+                        .get(name)
+                        .and_then(|labels| labels.iter().position(|l| l == label))
+                        .ok_or_else(|| DobbyError::UnknownLabel(label.clone(), name.clone()))?;
+                    bytes.push(index as u8);
+                }
+                value => bytes.extend_from_slice(&value.clone().into_bytes()),
+            }
         }
+        Ok(bytes)
+    }
+
+    pub fn insert(&mut self, values: ColumnSet) -> Result<ColumnSet, DobbyError> {
+        let values = self.coerce(values)?;
+        let mut row = vec![LIVE];
+        row.extend(self.encode(&self.columns, &self.labels, &values)?);
 
         self.file
             .seek(SeekFrom::End(0))
@@ -119,36 +259,43 @@ impl Table {
         Ok(values)
     }
 
-    pub fn select(
+    pub fn rows(
         &mut self,
         columns: Vec<String>,
         conditions: ColumnSet,
-    ) -> Result<Vec<ColumnSet>, DobbyError> {
-        let conditions = self.coerce(conditions)?;
-        let mut selected = Vec::new();
-        self.file
-            .seek(SeekFrom::Start(0))
-            .map_err(DobbyError::IoError)?;
-        while let Some(row) = self.next_row() {
-            let Row { mut row, .. } = row.map_err(DobbyError::IoError)?;
-
-            if !self.check_conditions(&row, &conditions)? {
-                continue;
-            }
+    ) -> Result<Rows<'_>, DobbyError> {
+        self.rows_from(columns, conditions, 0)
+    }
 
-            for column in &columns {
-                if !row.contains_key(column) {
-                    return Err(DobbyError::ColumnNotFound(
-                        column.clone(),
-                        self.name.clone(),
-                    ));
-                }
+    /// Reads the rows from the `offset` into the file on, which is where
+    /// a previous iterator stopped, or 0 for the start
+    pub fn rows_from(
+        &mut self,
+        columns: Vec<String>,
+        conditions: ColumnSet,
+        offset: u64,
+    ) -> Result<Rows<'_>, DobbyError> {
+        let conditions = self.coerce_conditions(conditions)?;
+        for column in &columns {
+            if !self.columns.iter().any(|(name, _)| name == column) {
+                return Err(DobbyError::ColumnNotFound(
+                    column.clone(),
+                    self.name.clone(),
+                ));
             }
-
-            row.retain(|key, _| columns.is_empty() || columns.contains(key));
-            selected.push(row);
         }
-        Ok(selected)
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .map_err(DobbyError::IoError)?;
+        Ok(Rows { table: self, columns, conditions })
+    }
+
+    pub fn select(
+        &mut self,
+        columns: Vec<String>,
+        conditions: ColumnSet,
+    ) -> Result<Vec<ColumnSet>, DobbyError> {
+        self.rows(columns, conditions)?.collect()
     }
 
     pub fn update(
@@ -157,7 +304,7 @@ impl Table {
         conditions: ColumnSet,
     ) -> Result<Vec<ColumnSet>, DobbyError> {
         let set = self.coerce(set)?;
-        let conditions = self.coerce(conditions)?;
+        let conditions = self.coerce_conditions(conditions)?;
         let mut updated = Vec::new();
         let eof = self
             .file
@@ -192,14 +339,14 @@ impl Table {
             if was_updated {
                 updated.push(row.clone());
                 self.insert(row)?;
-                self.delete_at(offset).map_err(DobbyError::IoError)?;
+                self.mark_at(offset, UPDATED).map_err(DobbyError::IoError)?;
             }
         }
         Ok(updated)
     }
 
     pub fn delete(&mut self, conditions: ColumnSet) -> Result<Vec<ColumnSet>, DobbyError> {
-        let conditions = self.coerce(conditions)?;
+        let conditions = self.coerce_conditions(conditions)?;
         let mut deleted = Vec::new();
         self.file
             .seek(SeekFrom::Start(0))
@@ -210,12 +357,102 @@ impl Table {
                 continue;
             }
             deleted.push(row);
-            self.delete_at(offset).map_err(DobbyError::IoError)?;
+            self.mark_at(offset, DELETED).map_err(DobbyError::IoError)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Deleted rows that match the conditions and can still be restored
+    pub fn deleted(&mut self, conditions: ColumnSet) -> Result<Vec<ColumnSet>, DobbyError> {
+        let conditions = self.coerce_conditions(conditions)?;
+        let mut deleted = Vec::new();
+        self.file
+            .seek(SeekFrom::Start(0))
+            .map_err(DobbyError::IoError)?;
+        while let Some(record) = self.next_record() {
+            let (mark, Row { row, .. }) = record.map_err(DobbyError::IoError)?;
+            if mark == DELETED && self.check_conditions(&row, &conditions)? {
+                deleted.push(row);
+            }
         }
         Ok(deleted)
     }
 
-    pub fn drop(&mut self) -> Result<(), DobbyError> {
-        self.file.set_len(0).map_err(DobbyError::IoError)
+    pub fn undelete(&mut self, conditions: ColumnSet) -> Result<Vec<ColumnSet>, DobbyError> {
+        let conditions = self.coerce_conditions(conditions)?;
+        let mut restored = Vec::new();
+        self.file
+            .seek(SeekFrom::Start(0))
+            .map_err(DobbyError::IoError)?;
+        while let Some(record) = self.next_record() {
+            let (mark, Row { offset, row }) = record.map_err(DobbyError::IoError)?;
+            if mark != DELETED || !self.check_conditions(&row, &conditions)? {
+                continue;
+            }
+            restored.push(row);
+            self.mark_at(offset, LIVE).map_err(DobbyError::IoError)?;
+        }
+        Ok(restored)
     }
+
+    /// Whether the table has no rows, neither live nor deleted ones
+    pub fn is_empty(&mut self) -> Result<bool, DobbyError> {
+        self.file
+            .seek(SeekFrom::Start(0))
+            .map_err(DobbyError::IoError)?;
+        while let Some(record) = self.next_record() {
+            let (mark, _) = record.map_err(DobbyError::IoError)?;
+            if mark != UPDATED {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Converts every record to the new `columns` layout and enum `labels` and writes it into `file`,
+    /// which then replaces the table's own file. Replaced versions of updated rows
+    /// that fail to convert are discarded, since they can't be restored anyway.
+    pub fn rewrite<F>(
+        &mut self,
+        columns: Vec<(String, DataType)>,
+        labels: HashMap<String, Vec<String>>,
+        file: File,
+        mut convert: F,
+    ) -> Result<(), DobbyError>
+    where
+        F: FnMut(ColumnSet) -> Result<ColumnSet, DobbyError>,
+    {
+        self.file
+            .seek(SeekFrom::Start(0))
+            .map_err(DobbyError::IoError)?;
+        let mut writer = io::BufWriter::new(file);
+        while let Some(record) = self.next_record() {
+            let (mark, Row { row, .. }) = record.map_err(DobbyError::IoError)?;
+            let bytes = convert(row).and_then(|row| self.encode(&columns, &labels, &row));
+            match bytes {
+                Ok(bytes) => {
+                    writer.write_all(&[mark]).map_err(DobbyError::IoError)?;
+                    writer.write_all(&bytes).map_err(DobbyError::IoError)?;
+                }
+                Err(_) if mark == UPDATED => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        self.file = writer
+            .into_inner()
+            .map_err(|e| DobbyError::IoError(e.into_error()))?;
+        self.columns = columns;
+        self.labels = labels;
+        Ok(())
+    }
+}
+
+/// Reads the index of an enum value and looks up its label
+fn read_label(file: &mut File, labels: Option<&Vec<String>>) -> Result<TypedValue, io::Error> {
+    let mut index = [0];
+    file.read_exact(&mut index)?;
+    labels
+        .and_then(|labels| labels.get(index[0] as usize))
+        .map(|label| TypedValue::Enum(label.clone()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid enum label"))
 }
@@ -7,6 +7,7 @@ fn table() -> Table {
             ("id".into(), DataType::Int),
             ("price".into(), DataType::Float),
         ],
+        labels: HashMap::new(),
         file: tempfile::tempfile().unwrap(),
     }
 }
@@ -114,3 +115,350 @@ fn delete() -> Result<(), DobbyError> {
 
     Ok(())
 }
+
+#[test]
+fn rows() -> Result<(), DobbyError> {
+    let mut table = table();
+    for id in 0..3 {
+        table.insert([("id".into(), id.into()), ("price".into(), 1.0.into())].into())?;
+    }
+
+    let mut rows = table.rows(vec!["id".into()], [].into())?;
+    assert_eq!(
+        rows.next().unwrap()?,
+        [("id".into(), TypedValue::Int(0))].into()
+    );
+    let offset = rows.offset()?;
+    assert_eq!(rows.count(), 2);
+
+    // reading can go on from where it stopped
+    let rest: Vec<_> = table
+        .rows_from(vec!["id".into()], [].into(), offset)?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(
+        rest,
+        vec![
+            [("id".into(), TypedValue::Int(1))].into(),
+            [("id".into(), TypedValue::Int(2))].into(),
+        ]
+    );
+
+    assert!(matches!(
+        table.rows(vec!["color".into()], [].into()),
+        Err(DobbyError::ColumnNotFound(_, _))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn rewrite() -> Result<(), DobbyError> {
+    let mut table = table();
+    for id in 0..2 {
+        table.insert([("id".into(), id.into()), ("price".into(), 1.0.into())].into())?;
+    }
+    table.delete([("id".into(), TypedValue::Int(0))].into())?;
+
+    let columns = vec![
+        ("id".into(), DataType::Int),
+        ("color".into(), DataType::String),
+    ];
+    table.rewrite(
+        columns,
+        HashMap::new(),
+        tempfile::tempfile().unwrap(),
+        |mut row| {
+            row.remove("price");
+            row.insert("color".into(), "red".into());
+            Ok(row)
+        },
+    )?;
+
+    let rows = table.select(vec![], [].into())?;
+    assert_eq!(
+        rows,
+        vec![[("id".into(), 1.into()), ("color".into(), "red".into())].into()]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn undelete() -> Result<(), DobbyError> {
+    let mut table = table();
+    for id in 0..3 {
+        table.insert([("id".into(), id.into()), ("price".into(), 1.0.into())].into())?;
+    }
+    table.update(
+        [("price".into(), TypedValue::Float(2.0))].into(),
+        [("id".into(), TypedValue::Int(0))].into(),
+    )?;
+    table.delete([].into())?;
+
+    // the row replaced by the update is not restorable
+    assert_eq!(table.deleted([].into())?.len(), 3);
+
+    let restored = table.undelete([("id".into(), TypedValue::Int(0))].into())?;
+    assert_eq!(restored.len(), 1);
+    assert_eq!(restored[0]["price"], TypedValue::Float(2.0));
+
+    assert_eq!(table.select(vec![], [].into())?, restored);
+    assert_eq!(table.deleted([].into())?.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn temporal() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![
+            ("done".into(), DataType::Bool),
+            ("due".into(), DataType::Date),
+            ("created".into(), DataType::Timestamp),
+        ],
+        labels: HashMap::new(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    table.insert(
+        [
+            ("done".into(), "TRUE".into()),
+            ("due".into(), "2024-02-29".into()),
+            ("created".into(), "2024-02-29T12:30:00.1234567+02:00".into()),
+        ]
+        .into(),
+    )?;
+
+    let rows = table.select(vec![], [("due".into(), "2024-02-29".into())].into())?;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["done"], true.into());
+    // converted to UTC and cut to microseconds
+    assert_eq!(
+        rows[0]["created"].to_string(),
+        "2024-02-29T10:30:00.123456Z"
+    );
+
+    assert!(table
+        .insert([("due".into(), "2024-02-30".into())].into())
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn intervals() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![
+            ("slots".into(), DataType::IntInvl),
+            ("load".into(), DataType::FloatInvl),
+            ("shift".into(), DataType::TimestampInvl),
+        ],
+        labels: HashMap::new(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    table.insert(
+        [
+            ("slots".into(), "-5..-1".into()),
+            ("load".into(), TypedValue::IntInvl(1, 2)),
+            (
+                "shift".into(),
+                "2024-02-29T08:00:00Z..2024-02-29 16:00:00".into(),
+            ),
+        ]
+        .into(),
+    )?;
+
+    let rows = table.select(vec![], [("slots".into(), "-5..-1".into())].into())?;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["load"], TypedValue::FloatInvl(1.0, 2.0));
+    assert_eq!(
+        rows[0]["shift"].to_string(),
+        "2024-02-29T08:00:00Z..2024-02-29T16:00:00Z"
+    );
+
+    let reversed = [
+        ("slots".into(), "3..1".into()),
+        ("load".into(), "0.5..0.25".into()),
+        ("shift".into(), "2024-02-29..2024-02-28".into()),
+    ];
+    for (column, value) in reversed {
+        let mut row = rows[0].clone();
+        row.insert(column, value);
+        assert!(matches!(
+            table.insert(row),
+            Err(DobbyError::InvalidRange(_, _))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn bytes() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![
+            ("id".into(), DataType::Int),
+            ("blob".into(), DataType::Bytes),
+            ("name".into(), DataType::String),
+        ],
+        labels: HashMap::new(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    for id in 0..3 {
+        table.insert(
+            [
+                ("id".into(), id.into()),
+                (
+                    "blob".into(),
+                    TypedValue::Bytes(vec![0xff; 1000 * id as usize]),
+                ),
+                ("name".into(), format!("file{}", id).into()),
+            ]
+            .into(),
+        )?;
+    }
+
+    // the skipped blobs don't break the columns after them
+    let rows = table.select(vec!["name".into()], [("id".into(), 2.into())].into())?;
+    assert_eq!(rows, vec![[("name".into(), "file2".into())].into()]);
+
+    // base64, as it arrives in JSON
+    let rows = table.select(vec!["id".into()], [("blob".into(), "//8=".into())].into())?;
+    assert!(rows.is_empty());
+    let rows = table.select(vec![], [("blob".into(), "".into())].into())?;
+    assert_eq!(rows[0]["blob"], TypedValue::Bytes(vec![]));
+
+    Ok(())
+}
+
+#[test]
+fn json() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![("id".into(), DataType::Int), ("doc".into(), DataType::Json)],
+        labels: HashMap::new(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    let doc = r#"{ "name": "Dobby", "age": 42, "tags": ["elf", "free"], "owner": null }"#;
+    let doc = TypedValue::Json(serde_json::from_str(doc).unwrap());
+    table.insert([("id".into(), 1.into()), ("doc".into(), doc)].into())?;
+    table.insert(
+        [
+            ("id".into(), 2.into()),
+            ("doc".into(), TypedValue::IntInvl(1, 2)),
+        ]
+        .into(),
+    )?;
+    // text is a JSON string, even when it reads as JSON
+    table.insert([("id".into(), 3.into()), ("doc".into(), "{".into())].into())?;
+
+    // stored compactly, with the keys sorted
+    let rows = table.select(vec!["doc".into()], [("id".into(), 1.into())].into())?;
+    assert_eq!(
+        rows[0]["doc"].to_string(),
+        r#"{"age":42,"name":"Dobby","owner":null,"tags":["elf","free"]}"#
+    );
+
+    let ids = |table: &mut Table, key: &str, value: TypedValue| {
+        table
+            .select(vec!["id".into()], [(key.to_string(), value)].into())
+            .map(|rows| rows.len())
+    };
+    assert_eq!(ids(&mut table, "doc.name", "Dobby".into())?, 1);
+    assert_eq!(ids(&mut table, "doc.age", 42.into())?, 1);
+    assert_eq!(ids(&mut table, "doc.age", "42".into())?, 0);
+    assert_eq!(ids(&mut table, "doc.tags.1", "free".into())?, 1);
+    assert_eq!(
+        ids(
+            &mut table,
+            "doc.owner",
+            TypedValue::Json(serde_json::Value::Null)
+        )?,
+        1
+    );
+    assert_eq!(ids(&mut table, "doc", "{".into())?, 1);
+    assert_eq!(ids(&mut table, "doc.0", 1.into())?, 1);
+    assert!(ids(&mut table, "id.name", "Dobby".into()).is_err());
+    assert!(ids(&mut table, "doc.na-me", "Dobby".into()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn enums() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![
+            ("id".into(), DataType::Int),
+            ("status".into(), DataType::Enum),
+        ],
+        labels: [("status".into(), vec!["open".into(), "closed".into()])].into(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    for (id, status) in [(1, "open"), (2, "closed"), (3, "open")] {
+        table.insert([("id".into(), id.into()), ("status".into(), status.into())].into())?;
+    }
+    assert!(matches!(
+        table.insert([("id".into(), 4.into()), ("status".into(), "lost".into())].into()),
+        Err(DobbyError::UnknownLabel(_, _))
+    ));
+
+    let rows = table.select(vec!["id".into()], [("status".into(), "open".into())].into())?;
+    assert_eq!(rows.len(), 2);
+    let rows = table.select(vec!["status".into()], [("id".into(), 2.into())].into())?;
+    assert_eq!(rows[0]["status"], TypedValue::Enum("closed".into()));
+
+    Ok(())
+}
+
+#[test]
+fn decimals() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![
+            ("id".into(), DataType::Int),
+            ("price".into(), DataType::Decimal),
+        ],
+        labels: HashMap::new(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    // more digits than a float holds
+    let price = "12345678901234567890.12345678";
+    table.insert([("id".into(), 1.into()), ("price".into(), price.into())].into())?;
+    table.insert([("id".into(), 2.into()), ("price".into(), "0.10".into())].into())?;
+
+    let rows = table.select(vec!["price".into()], [("id".into(), 1.into())].into())?;
+    assert_eq!(rows[0]["price"].to_string(), price);
+    // compared exactly, whatever the scale
+    let rows = table.select(vec!["id".into()], [("price".into(), "0.1".into())].into())?;
+    assert_eq!(rows, vec![[("id".into(), 2.into())].into()]);
+
+    Ok(())
+}
+
+#[test]
+fn uuids() -> Result<(), DobbyError> {
+    let mut table = Table {
+        name: "test".into(),
+        columns: vec![("id".into(), DataType::Uuid), ("n".into(), DataType::Int)],
+        labels: HashMap::new(),
+        file: tempfile::tempfile().unwrap(),
+    };
+    let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    table.insert([("id".into(), id.into()), ("n".into(), 1.into())].into())?;
+    assert!(table
+        .insert([("id".into(), "67e55044".into()), ("n".into(), 2.into())].into())
+        .is_err());
+    // stored as its 16 bytes, after the 8 of the first column
+    assert_eq!(table.file.metadata()?.len(), 1 + 16 + 8);
+
+    let rows = table.select(
+        vec!["n".into()],
+        [("id".into(), id.to_uppercase().into())].into(),
+    )?;
+    assert_eq!(rows, vec![[("n".into(), 1.into())].into()]);
+
+    Ok(())
+}
@@ -2,12 +2,22 @@ use std::collections::HashMap;
 use std::fmt;
 use std::io;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+#[cfg(feature = "sqlite")]
 use rusqlite::types::ToSqlOutput;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
 
 pub type ColumnSet = HashMap<String, TypedValue>;
 
+/// Fixed-width text form of timestamps, which sorts chronologically
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
 #[derive(Debug, Error)]
 pub enum DobbyError {
     #[error("Table {0} already exists")]
@@ -16,6 +26,9 @@ pub enum DobbyError {
     #[error("Table {0} not found")]
     TableNotFound(String),
 
+    #[error("A table named {0} is already in the trash: restore or purge it first")]
+    TableInTrash(String),
+
     #[error("Column {0} already exists in table {1}")]
     ColumnAlreadyExists(String, String),
 
@@ -40,9 +53,76 @@ pub enum DobbyError {
     #[error("Invalid range: {0} > {1}")]
     InvalidRange(String, String),
 
+    #[error("Float {0} can't be stored, only finite floats can")]
+    NonFiniteFloat(f64),
+
+    #[error("Rule {0} can't apply to column {1} of datatype {2:?}")]
+    InvalidRule(Rule, String, DataType),
+
+    #[error("Value {0} of column {1} breaks the rule {2}")]
+    RuleViolated(TypedValue, String, Rule),
+
+    #[error("Invalid rule: {0}")]
+    UnknownRule(String),
+
+    #[error("Column {0} can't reference {1}: only the key column of an existing table can be referenced")]
+    InvalidReference(String, String),
+
+    #[error("Value {0} of column {1} doesn't match any row of table {2}")]
+    MissingReference(TypedValue, String, String),
+
+    #[error("Table {0} is referenced by table {1}")]
+    Referenced(String, String),
+
+    #[error("Column {0} of table {1} can't auto-increment")]
+    InvalidAutoIncrement(String, String),
+
+    #[error("Auto-increment column {0} of table {1} can't go past the largest int")]
+    AutoIncrementExhausted(String, String),
+
+    #[error("Value {0} of key column {1} is already taken in table {2}")]
+    DuplicateKey(TypedValue, String, String),
+
+    #[error("Enum column {0} needs between 1 and 256 distinct labels")]
+    InvalidLabels(String),
+
+    #[error("{0} is not one of the labels of column {1}")]
+    UnknownLabel(String, String),
+
+    #[error("Decimal column {0} needs a precision between 1 and 28 and a scale of at most the precision")]
+    InvalidPrecision(String),
+
+    #[error("Value {0} of column {1} doesn't fit decimal({2})")]
+    PrecisionExceeded(TypedValue, String, Precision),
+
+    #[error("Column {0} of table {1} can't be generated: only uuid columns can")]
+    InvalidGenerated(String, String),
+
+    #[error("Can't convert {0} to {1:?}: rule {2} isn't allowed in {3} coercion")]
+    CoercionNotAllowed(TypedValue, DataType, CoercionRule, Coercion),
+
+    #[error("Can't convert {0} to {1:?} by rule {2}")]
+    CoercionFailed(TypedValue, DataType, CoercionRule),
+
+    #[error("Unknown coercion {0}: expected strict, lenient or extended")]
+    UnknownCoercion(String),
+
+    #[error("Can't convert {} rows to the new datatypes: {0:?}", .0.len())]
+    ConversionFailed(Vec<ColumnSet>),
+
+    #[error("Refusing to modify every row in table {0} without an explicit `all` flag")]
+    UnfilteredQuery(String),
+
+    #[error("Table {0} was changed while its rows were being read")]
+    TableChanged(String),
+
+    #[error("{0} is not supported by this backend")]
+    Unsupported(String),
+
     #[error("IO Error: {0}")]
     IoError(#[from] std::io::Error),
 
+    #[cfg(feature = "sqlite")]
     #[error("SQL Error: {0}")]
     SqlError(#[from] rusqlite::Error),
 }
@@ -71,21 +151,71 @@ pub enum Query {
         table: String,
         set: ColumnSet,
         conditions: ColumnSet,
+        /// Confirms that empty `conditions` are intended to match every row
+        all: bool,
     },
     Delete {
         from: String,
         conditions: ColumnSet,
+        /// Confirms that empty `conditions` are intended to match every row
+        all: bool,
+    },
+    /// Deleted rows that can still be restored
+    ListDeleted {
+        from: String,
+        conditions: ColumnSet,
+    },
+    Undelete {
+        from: String,
+        conditions: ColumnSet,
+        /// Confirms that empty `conditions` are intended to match every deleted row
+        all: bool,
     },
     Create {
         table: String,
         columns: Vec<(String, DataType)>,
+        /// Int column that gets the next value of a counter when omitted on insert
+        auto_increment: Option<String>,
+        /// Values to insert when the columns are omitted
+        defaults: ColumnSet,
+        rules: HashMap<String, Vec<Rule>>,
+        references: HashMap<String, Reference>,
+        /// The labels of the enum columns
+        labels: HashMap<String, Vec<String>>,
+        /// The precisions of the decimal columns that have one
+        precisions: HashMap<String, Precision>,
+        /// Uuid columns that get a new uuid of the version when omitted on insert
+        generated: HashMap<String, UuidVersion>,
     },
     Drop {
         table: String,
     },
+    Undrop {
+        table: String,
+    },
+    ListTrash,
+    /// Removes dropped tables for good - all of them, if `table` is `None`
+    Purge {
+        table: Option<String>,
+    },
+    Rename {
+        table: String,
+        to: String,
+    },
     Alter {
         table: String,
         rename: HashMap<String, String>,
+        add: Vec<(String, DataType)>,
+        drop: Vec<String>,
+        retype: HashMap<String, DataType>,
+        /// Defaults of the added columns, which also fill them in the existing rows
+        defaults: ColumnSet,
+        /// The labels of the added enum columns
+        labels: HashMap<String, Vec<String>>,
+        /// The precisions of the added decimal columns that have one
+        precisions: HashMap<String, Precision>,
+        /// Added uuid columns that get a new uuid of the version, in the existing rows too
+        generated: HashMap<String, UuidVersion>,
     },
 }
 
@@ -98,6 +228,22 @@ pub enum TypedValue {
     String(String),
     CharInvl(char, char),
     StringInvl(String, String),
+    Bool(bool),
+    Date(NaiveDate),
+    /// UTC, with microsecond precision
+    Timestamp(DateTime<Utc>),
+    IntInvl(i64, i64),
+    FloatInvl(f64, f64),
+    TimestampInvl(DateTime<Utc>, DateTime<Utc>),
+    /// Base64 in JSON
+    Bytes(#[serde(with = "base64_bytes")] Vec<u8>),
+    Json(serde_json::Value),
+    /// Label of an enum column
+    Enum(String),
+    /// Exact, a string in JSON
+    Decimal(Decimal),
+    /// Hyphenated lowercase text in JSON
+    Uuid(Uuid),
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord)]
@@ -109,8 +255,345 @@ pub enum DataType {
     String = 3,
     CharInvl = 4,
     StringInvl = 5,
+    Bool = 6,
+    Date = 7,
+    Timestamp = 8,
+    IntInvl = 9,
+    FloatInvl = 10,
+    TimestampInvl = 11,
+    Bytes = 12,
+    Json = 13,
+    Enum = 14,
+    Decimal = 15,
+    Uuid = 16,
+}
+
+/// How the uuids of a column are generated when omitted on insert
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UuidVersion {
+    /// Random
+    V4,
+    /// Time-ordered, so that later ones sort after earlier ones
+    V7,
+}
+
+impl UuidVersion {
+    pub fn generate(&self) -> Uuid {
+        match self {
+            UuidVersion::V4 => Uuid::new_v4(),
+            UuidVersion::V7 => Uuid::now_v7(),
+        }
+    }
+}
+
+/// Which conversions values of queries can go through to match their columns
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Coercion {
+    /// Only the forms the datatypes are written in, like dates as text
+    Strict,
+    /// Also text to numbers and widening numbers
+    #[default]
+    Lenient,
+    /// Also numbers and dates to text and integral floats to ints
+    Extended,
+}
+
+impl Coercion {
+    pub fn allows(&self, rule: CoercionRule) -> bool {
+        match rule {
+            CoercionRule::Text | CoercionRule::Json => true,
+            CoercionRule::ParseNumber | CoercionRule::Widening | CoercionRule::Stringify => {
+                *self >= Coercion::Lenient
+            }
+            CoercionRule::Format | CoercionRule::Narrowing => *self == Coercion::Extended,
+        }
+    }
+}
+
+impl fmt::Display for Coercion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Coercion::Strict => write!(f, "strict"),
+            Coercion::Lenient => write!(f, "lenient"),
+            Coercion::Extended => write!(f, "extended"),
+        }
+    }
+}
+
+impl std::str::FromStr for Coercion {
+    type Err = DobbyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Coercion::Strict),
+            "lenient" => Ok(Coercion::Lenient),
+            "extended" => Ok(Coercion::Extended),
+            _ => Err(DobbyError::UnknownCoercion(s.to_string())),
+        }
+    }
+}
+
+/// Kind of conversion of a value to another datatype
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoercionRule {
+    /// Text to the datatypes written as text, like dates, uuids or `a..b` intervals
+    Text,
+    /// Any value to a JSON document
+    Json,
+    /// Text to ints, floats and bools
+    ParseNumber,
+    /// Ints to floats and decimals, floats to decimals, dates to timestamps,
+    /// int intervals to float intervals, and bools to and from 0 and 1
+    Widening,
+    /// JSON documents, labels, decimals and uuids to text
+    Stringify,
+    /// Numbers, bools, dates and timestamps to text
+    Format,
+    /// Integral floats and decimals to ints, and decimals to floats
+    Narrowing,
+}
+
+impl CoercionRule {
+    /// The rule converting the value to the datatype goes by, if any
+    pub fn of(value: &TypedValue, to: DataType) -> Option<CoercionRule> {
+        use DataType as T;
+        use TypedValue as V;
+        match (value, to) {
+            (_, T::Json) => Some(CoercionRule::Json),
+            (V::Char(_) | V::String(_), T::Int | T::Float | T::Bool) => {
+                Some(CoercionRule::ParseNumber)
+            }
+            (V::Char(_) | V::String(_), _) => Some(CoercionRule::Text),
+            (
+                V::CharInvl(_, _) | V::StringInvl(_, _),
+                T::CharInvl | T::StringInvl | T::IntInvl | T::FloatInvl | T::TimestampInvl,
+            ) => Some(CoercionRule::Text),
+            (V::Int(_), T::Float | T::Decimal | T::Bool)
+            | (V::Float(_), T::Decimal)
+            | (V::Bool(_), T::Int)
+            | (V::Date(_), T::Timestamp)
+            | (V::IntInvl(_, _), T::FloatInvl) => Some(CoercionRule::Widening),
+            (V::Json(_) | V::Enum(_) | V::Decimal(_) | V::Uuid(_), T::String) => {
+                Some(CoercionRule::Stringify)
+            }
+            (V::Int(_) | V::Float(_) | V::Bool(_) | V::Date(_) | V::Timestamp(_), T::String) => {
+                Some(CoercionRule::Format)
+            }
+            (V::Float(_) | V::Decimal(_), T::Int) | (V::Decimal(_), T::Float) => {
+                Some(CoercionRule::Narrowing)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CoercionRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            CoercionRule::Text => write!(f, "text"),
+            CoercionRule::Json => write!(f, "json"),
+            CoercionRule::ParseNumber => write!(f, "parse_number"),
+            CoercionRule::Widening => write!(f, "widening"),
+            CoercionRule::Stringify => write!(f, "stringify"),
+            CoercionRule::Format => write!(f, "format"),
+            CoercionRule::Narrowing => write!(f, "narrowing"),
+        }
+    }
+}
+
+/// The number of digits of a decimal column, in total and after the point
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Precision {
+    pub precision: u32,
+    pub scale: u32,
+}
+
+impl Precision {
+    /// Decimals have 96-bit mantissas, which hold any 28 digits
+    pub const MAX_DIGITS: u32 = 28;
+
+    pub fn is_valid(&self) -> bool {
+        (1..=Self::MAX_DIGITS).contains(&self.precision) && self.scale <= self.precision
+    }
+
+    /// Rescales the decimal to the scale, unless that would round it
+    /// or leave it with too many digits
+    pub fn fit(&self, mut decimal: Decimal) -> Option<Decimal> {
+        if decimal.round_dp(self.scale) != decimal {
+            return None;
+        }
+        decimal.rescale(self.scale);
+        (decimal.mantissa().unsigned_abs() < 10u128.pow(self.precision)).then_some(decimal)
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{},{}", self.precision, self.scale)
+    }
+}
+
+/// Constraint on the values of a column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    Min(f64),
+    Max(f64),
+    MaxLength(usize),
+    /// Regular expression that has to match the value
+    Pattern(String),
+    /// Interval bounds can't be closer than this
+    MinWidth(f64),
+    /// Interval bounds can't be further than this
+    MaxWidth(f64),
+}
+
+impl Rule {
+    pub fn applies_to(&self, data_type: DataType) -> bool {
+        match self {
+            Rule::Min(_) | Rule::Max(_) => matches!(
+                data_type,
+                DataType::Int | DataType::Float | DataType::Decimal
+            ),
+            Rule::MaxLength(_) => data_type == DataType::String,
+            Rule::Pattern(pattern) => {
+                matches!(data_type, DataType::Char | DataType::String)
+                    && regex::Regex::new(pattern).is_ok()
+            }
+            Rule::MinWidth(_) | Rule::MaxWidth(_) => matches!(
+                data_type,
+                DataType::CharInvl
+                    | DataType::IntInvl
+                    | DataType::FloatInvl
+                    | DataType::TimestampInvl
+            ),
+        }
+    }
+
+    /// Checks the value, which has to be of a datatype the rule applies to
+    pub fn check(&self, value: &TypedValue) -> bool {
+        let number = match value {
+            TypedValue::Int(i) => *i as f64,
+            TypedValue::Float(f) => *f,
+            _ => f64::NAN,
+        };
+        let width = match value {
+            TypedValue::CharInvl(c1, c2) => (*c2 as u32 - *c1 as u32) as f64,
+            TypedValue::IntInvl(i1, i2) => (*i2 as f64) - (*i1 as f64),
+            TypedValue::FloatInvl(f1, f2) => f2 - f1,
+            // in seconds, which span the whole range of timestamps,
+            // unlike microseconds
+            TypedValue::TimestampInvl(t1, t2) => {
+                let width = *t2 - *t1;
+                width.num_seconds() as f64 + width.subsec_nanos() as f64 / 1e9
+            }
+            _ => f64::NAN,
+        };
+        // decimals compare exactly to the bounds
+        if let (TypedValue::Decimal(d), Rule::Min(bound) | Rule::Max(bound)) = (value, self) {
+            return decimal_from_f64(*bound).is_some_and(|bound| match self {
+                Rule::Min(_) => *d >= bound,
+                _ => *d <= bound,
+            });
+        }
+        match self {
+            Rule::Min(min) => number >= *min,
+            Rule::Max(max) => number <= *max,
+            Rule::MaxLength(length) => value.to_string().chars().count() <= *length,
+            Rule::Pattern(pattern) => regex::Regex::new(pattern)
+                .map(|regex| regex.is_match(&value.to_string()))
+                .unwrap_or(false),
+            Rule::MinWidth(min) => width >= *min,
+            Rule::MaxWidth(max) => width <= *max,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Rule::Min(min) => write!(f, "min:{}", min),
+            Rule::Max(max) => write!(f, "max:{}", max),
+            Rule::MaxLength(length) => write!(f, "max_length:{}", length),
+            Rule::Pattern(pattern) => write!(f, "pattern:{}", pattern),
+            Rule::MinWidth(min) => write!(f, "min_width:{}", min),
+            Rule::MaxWidth(max) => write!(f, "max_width:{}", max),
+        }
+    }
+}
+
+impl TryFrom<&str> for Rule {
+    type Error = DobbyError;
+
+    /// Parses rules in the `name:value` format, like `max_length:10`
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let invalid = || DobbyError::UnknownRule(s.to_string());
+        let (name, value) = s.split_once(':').ok_or_else(invalid)?;
+        let number = || value.parse::<f64>().map_err(|_| invalid());
+        match name {
+            "min" => Ok(Rule::Min(number()?)),
+            "max" => Ok(Rule::Max(number()?)),
+            "max_length" => Ok(Rule::MaxLength(value.parse().map_err(|_| invalid())?)),
+            "pattern" => Ok(Rule::Pattern(value.to_string())),
+            "min_width" => Ok(Rule::MinWidth(number()?)),
+            "max_width" => Ok(Rule::MaxWidth(number()?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Reference from a column to the key column of another table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+    pub table: String,
+    pub column: String,
+    #[serde(default)]
+    pub on_delete: OnDelete,
+}
+
+/// What happens to the referencing rows when the referenced row is deleted
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnDelete {
+    /// The deletion fails
+    #[default]
+    Restrict,
+    /// The referencing rows are deleted too
+    Cascade,
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}.{}", self.table, self.column)
+    }
+}
+
+impl TryFrom<&str> for Reference {
+    type Error = DobbyError;
+
+    /// Parses references in the `table.column[:cascade]` format
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let (target, on_delete) = match s.split_once(':') {
+            Some((target, "cascade")) => (target, OnDelete::Cascade),
+            Some((target, "restrict")) => (target, OnDelete::Restrict),
+            Some(_) => return Err(DobbyError::InvalidName(s.into())),
+            None => (s, OnDelete::Restrict),
+        };
+        let (table, column) = target
+            .split_once('.')
+            .ok_or_else(|| DobbyError::InvalidName(s.into()))?;
+        Ok(Reference {
+            table: table.into(),
+            column: column.into(),
+            on_delete,
+        })
+    }
 }
 
+#[cfg(feature = "sqlite")]
 impl rusqlite::ToSql for TypedValue {
     fn to_sql(&self) -> Result<ToSqlOutput<'_>, rusqlite::Error> {
         match self {
@@ -120,6 +603,23 @@ impl rusqlite::ToSql for TypedValue {
             TypedValue::Char(c) => Ok(ToSqlOutput::from(c.to_string())),
             TypedValue::StringInvl(s1, s2) => Ok(ToSqlOutput::from(format!("{}..{}", s1, s2))),
             TypedValue::CharInvl(c1, c2) => Ok(ToSqlOutput::from(format!("{}..{}", c1, c2))),
+            TypedValue::Bool(b) => b.to_sql(),
+            TypedValue::Date(d) => Ok(ToSqlOutput::from(d.to_string())),
+            TypedValue::Timestamp(t) => {
+                Ok(ToSqlOutput::from(t.format(TIMESTAMP_FORMAT).to_string()))
+            }
+            TypedValue::IntInvl(i1, i2) => Ok(ToSqlOutput::from(format!("{}..{}", i1, i2))),
+            TypedValue::FloatInvl(f1, f2) => Ok(ToSqlOutput::from(format!("{}..{}", f1, f2))),
+            TypedValue::TimestampInvl(t1, t2) => Ok(ToSqlOutput::from(format!(
+                "{}..{}",
+                t1.format(TIMESTAMP_FORMAT),
+                t2.format(TIMESTAMP_FORMAT)
+            ))),
+            TypedValue::Bytes(b) => b.to_sql(),
+            TypedValue::Json(j) => Ok(ToSqlOutput::from(j.to_string())),
+            TypedValue::Enum(label) => label.to_sql(),
+            TypedValue::Decimal(d) => Ok(ToSqlOutput::from(d.to_string())),
+            TypedValue::Uuid(u) => Ok(ToSqlOutput::from(u.to_string())),
         }
     }
 }
@@ -127,19 +627,33 @@ impl rusqlite::ToSql for TypedValue {
 impl TypedValue {
     pub fn validate(&self) -> Result<(), DobbyError> {
         match self {
-            TypedValue::CharInvl(c1, c2) => {
-                if c1 > c2 {
-                    return Err(DobbyError::InvalidRange(c1.to_string(), c2.to_string()));
-                }
+            TypedValue::CharInvl(c1, c2) if c1 > c2 => {
+                Err(DobbyError::InvalidRange(c1.to_string(), c2.to_string()))
             }
-            TypedValue::StringInvl(s1, s2) => {
-                if s1 > s2 {
-                    return Err(DobbyError::InvalidRange(s1.to_string(), s2.to_string()));
-                }
+            TypedValue::StringInvl(s1, s2) if s1 > s2 => {
+                Err(DobbyError::InvalidRange(s1.to_string(), s2.to_string()))
+            }
+            TypedValue::IntInvl(i1, i2) if i1 > i2 => {
+                Err(DobbyError::InvalidRange(i1.to_string(), i2.to_string()))
+            }
+            // NaN and infinities have no JSON form, and SQLite turns NaN into NULL
+            TypedValue::Float(f) if !f.is_finite() => Err(DobbyError::NonFiniteFloat(*f)),
+            TypedValue::FloatInvl(f1, f2) if !f1.is_finite() || !f2.is_finite() => {
+                Err(DobbyError::NonFiniteFloat(if f1.is_finite() {
+                    *f2
+                } else {
+                    *f1
+                }))
+            }
+            TypedValue::FloatInvl(f1, f2) if f1 > f2 => {
+                Err(DobbyError::InvalidRange(f1.to_string(), f2.to_string()))
             }
-            _ => {}
+            TypedValue::TimestampInvl(t1, t2) if t1 > t2 => Err(DobbyError::InvalidRange(
+                TypedValue::Timestamp(*t1).to_string(),
+                TypedValue::Timestamp(*t2).to_string(),
+            )),
+            _ => Ok(()),
         }
-        Ok(())
     }
 
     pub fn data_type(&self) -> DataType {
@@ -150,17 +664,23 @@ impl TypedValue {
             TypedValue::String(_) => DataType::String,
             TypedValue::CharInvl(_, _) => DataType::CharInvl,
             TypedValue::StringInvl(_, _) => DataType::StringInvl,
+            TypedValue::Bool(_) => DataType::Bool,
+            TypedValue::Date(_) => DataType::Date,
+            TypedValue::Timestamp(_) => DataType::Timestamp,
+            TypedValue::IntInvl(_, _) => DataType::IntInvl,
+            TypedValue::FloatInvl(_, _) => DataType::FloatInvl,
+            TypedValue::TimestampInvl(_, _) => DataType::TimestampInvl,
+            TypedValue::Bytes(_) => DataType::Bytes,
+            TypedValue::Json(_) => DataType::Json,
+            TypedValue::Enum(_) => DataType::Enum,
+            TypedValue::Decimal(_) => DataType::Decimal,
+            TypedValue::Uuid(_) => DataType::Uuid,
         }
     }
 
     pub fn read<R: io::Read>(data_type: DataType, reader: &mut R) -> Result<Self, io::Error> {
         let mut read_string = || {
-            let mut length = [0; 8];
-            reader.read_exact(&mut length)?;
-            let length = u64::from_le_bytes(length);
-            let mut buf = vec![0; length as usize];
-            reader.read_exact(&mut buf)?;
-            String::from_utf8(buf)
+            String::from_utf8(read_bytes(reader)?)
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8 string"))
         };
 
@@ -187,15 +707,66 @@ impl TypedValue {
                 reader.read_exact(&mut buf)?;
                 Ok(TypedValue::CharInvl(char::from(buf[0]), char::from(buf[1])))
             }
+            DataType::Bool => {
+                let mut buf = [0; 1];
+                reader.read_exact(&mut buf)?;
+                Ok(TypedValue::Bool(buf[0] != 0))
+            }
+            DataType::Date => {
+                let mut buf = [0; 4];
+                reader.read_exact(&mut buf)?;
+                NaiveDate::from_num_days_from_ce_opt(i32::from_le_bytes(buf))
+                    .map(TypedValue::Date)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid date"))
+            }
+            DataType::Timestamp => Ok(TypedValue::Timestamp(read_timestamp(reader)?)),
+            DataType::IntInvl => {
+                let mut buf = [0; 16];
+                reader.read_exact(&mut buf)?;
+                let (i1, i2) = buf.split_at(8);
+                Ok(TypedValue::IntInvl(
+                    i64::from_le_bytes(i1.try_into().unwrap()),
+                    i64::from_le_bytes(i2.try_into().unwrap()),
+                ))
+            }
+            DataType::FloatInvl => {
+                let mut buf = [0; 16];
+                reader.read_exact(&mut buf)?;
+                let (f1, f2) = buf.split_at(8);
+                Ok(TypedValue::FloatInvl(
+                    f64::from_le_bytes(f1.try_into().unwrap()),
+                    f64::from_le_bytes(f2.try_into().unwrap()),
+                ))
+            }
+            DataType::TimestampInvl => Ok(TypedValue::TimestampInvl(
+                read_timestamp(reader)?,
+                read_timestamp(reader)?,
+            )),
+            DataType::Bytes => Ok(TypedValue::Bytes(read_bytes(reader)?)),
+            DataType::Json => serde_json::from_str(&read_string()?)
+                .map(TypedValue::Json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            DataType::Enum => unreachable!("Enum values are decoded by their table"),
+            DataType::Decimal => {
+                let mut buf = [0; 16];
+                reader.read_exact(&mut buf)?;
+                Ok(TypedValue::Decimal(Decimal::deserialize(buf)))
+            }
+            DataType::Uuid => {
+                let mut buf = [0; 16];
+                reader.read_exact(&mut buf)?;
+                Ok(TypedValue::Uuid(Uuid::from_bytes(buf)))
+            }
         }
     }
 
     pub fn into_bytes(self) -> Vec<u8> {
-        let convert_string = |s: String| {
-            let bytes = s.into_bytes();
+        let convert_pair = |a: [u8; 8], b: [u8; 8]| [a, b].concat();
+        let convert_bytes = |bytes: Vec<u8>| {
             let length = (bytes.len() as u64).to_le_bytes().to_vec();
             [length, bytes].concat()
         };
+        let convert_string = |s: String| convert_bytes(s.into_bytes());
 
         match self {
             TypedValue::Int(i) => i.to_le_bytes().to_vec(),
@@ -204,6 +775,20 @@ impl TypedValue {
             TypedValue::String(s) => convert_string(s),
             TypedValue::CharInvl(c1, c2) => vec![c1 as u8, c2 as u8],
             TypedValue::StringInvl(s1, s2) => [convert_string(s1), convert_string(s2)].concat(),
+            TypedValue::Bool(b) => vec![b as u8],
+            TypedValue::Date(d) => d.num_days_from_ce().to_le_bytes().to_vec(),
+            TypedValue::Timestamp(t) => t.timestamp_micros().to_le_bytes().to_vec(),
+            TypedValue::IntInvl(i1, i2) => convert_pair(i1.to_le_bytes(), i2.to_le_bytes()),
+            TypedValue::FloatInvl(f1, f2) => convert_pair(f1.to_le_bytes(), f2.to_le_bytes()),
+            TypedValue::TimestampInvl(t1, t2) => convert_pair(
+                t1.timestamp_micros().to_le_bytes(),
+                t2.timestamp_micros().to_le_bytes(),
+            ),
+            TypedValue::Bytes(b) => convert_bytes(b),
+            TypedValue::Json(j) => convert_string(j.to_string()),
+            TypedValue::Enum(_) => unreachable!("Enum values are encoded by their table"),
+            TypedValue::Decimal(d) => d.serialize().to_vec(),
+            TypedValue::Uuid(u) => u.as_bytes().to_vec(),
         }
     }
 
@@ -217,10 +802,10 @@ impl TypedValue {
         };
 
         if self.data_type() == to {
-            return Ok(self);
+            return Ok(self.without_negative_zero());
         }
 
-        match (&self, to) {
+        let coerced = match (&self, to) {
             (TypedValue::String(s), DataType::Char) => string_to_char(s).map(TypedValue::Char),
             (TypedValue::String(s), DataType::Int) => s
                 .parse::<i64>()
@@ -248,6 +833,83 @@ impl TypedValue {
                 }
             }
 
+            (TypedValue::String(s), DataType::IntInvl) => match s.split_once("..") {
+                Some((s1, s2)) => match (s1.parse(), s2.parse()) {
+                    (Ok(i1), Ok(i2)) => Ok(TypedValue::IntInvl(i1, i2)),
+                    _ => Err(DobbyError::InvalidValue(self, to)),
+                },
+                None => Err(DobbyError::InvalidValue(self, to)),
+            },
+            (TypedValue::String(s), DataType::FloatInvl) => match s.split_once("..") {
+                Some((s1, s2)) => match (s1.parse(), s2.parse()) {
+                    (Ok(f1), Ok(f2)) => Ok(TypedValue::FloatInvl(f1, f2)),
+                    _ => Err(DobbyError::InvalidValue(self, to)),
+                },
+                None => Err(DobbyError::InvalidValue(self, to)),
+            },
+            (TypedValue::String(s), DataType::TimestampInvl) => match s.split_once("..") {
+                Some((s1, s2)) => match (parse_timestamp(s1), parse_timestamp(s2)) {
+                    (Some(t1), Some(t2)) => Ok(TypedValue::TimestampInvl(t1, t2)),
+                    _ => Err(DobbyError::InvalidValue(self, to)),
+                },
+                None => Err(DobbyError::InvalidValue(self, to)),
+            },
+
+            (TypedValue::String(s), DataType::Bytes) => BASE64
+                .decode(s)
+                .map(TypedValue::Bytes)
+                .map_err(|_| DobbyError::InvalidValue(self, to)),
+
+            (TypedValue::Json(j), DataType::String) => Ok(TypedValue::String(j.to_string())),
+            // whether it's one of the labels is up to the column
+            (TypedValue::String(s), DataType::Enum) => Ok(TypedValue::Enum(s.clone())),
+            (TypedValue::Char(c), DataType::Enum) => Ok(TypedValue::Enum(c.to_string())),
+            (TypedValue::Enum(label), DataType::String) => Ok(TypedValue::String(label.clone())),
+            // whether it fits is up to the column as well
+            (TypedValue::String(s), DataType::Decimal) => Decimal::from_str_exact(s)
+                .map(TypedValue::Decimal)
+                .map_err(|_| DobbyError::InvalidValue(self, to)),
+            (TypedValue::Char(c), DataType::Decimal) => c
+                .to_digit(10)
+                .map(|digit| TypedValue::Decimal(digit.into()))
+                .ok_or(DobbyError::InvalidValue(self, to)),
+            (TypedValue::Int(i), DataType::Decimal) => Ok(TypedValue::Decimal((*i).into())),
+            (TypedValue::Float(f), DataType::Decimal) => decimal_from_f64(*f)
+                .map(TypedValue::Decimal)
+                .ok_or(DobbyError::InvalidValue(self, to)),
+            (TypedValue::String(s), DataType::Uuid) => Uuid::parse_str(s)
+                .map(TypedValue::Uuid)
+                .map_err(|_| DobbyError::InvalidValue(self, to)),
+            (TypedValue::Uuid(u), DataType::String) => Ok(TypedValue::String(u.to_string())),
+            (TypedValue::Decimal(d), DataType::String) => Ok(TypedValue::String(d.to_string())),
+            (TypedValue::Decimal(d), DataType::Float) => d
+                .to_f64()
+                .map(TypedValue::Float)
+                .ok_or(DobbyError::InvalidValue(self, to)),
+            (TypedValue::Decimal(d), DataType::Int) if d.fract().is_zero() => d
+                .to_i64()
+                .map(TypedValue::Int)
+                .ok_or(DobbyError::InvalidValue(self, to)),
+            // anything else is taken as it arrived in JSON, e.g. text as a JSON string
+            (TypedValue::Float(f), DataType::Json) if !f.is_finite() => {
+                Err(DobbyError::InvalidValue(self, to))
+            }
+            (v, DataType::Json) => serde_json::to_value(v)
+                .map(TypedValue::Json)
+                .map_err(|_| DobbyError::InvalidValue(self, to)),
+
+            (TypedValue::String(s), DataType::Bool) => match s.to_lowercase().as_str() {
+                "true" => Ok(TypedValue::Bool(true)),
+                "false" => Ok(TypedValue::Bool(false)),
+                _ => Err(DobbyError::InvalidValue(self, to)),
+            },
+            (TypedValue::String(s), DataType::Date) => NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(TypedValue::Date)
+                .map_err(|_| DobbyError::InvalidValue(self, to)),
+            (TypedValue::String(s), DataType::Timestamp) => parse_timestamp(s)
+                .map(TypedValue::Timestamp)
+                .ok_or(DobbyError::InvalidValue(self, to)),
+
             (TypedValue::Char(c), DataType::String) => Ok(TypedValue::String(c.to_string())),
             (TypedValue::Char(c), DataType::Int) => c
                 .to_string()
@@ -261,12 +923,163 @@ impl TypedValue {
                 .map_err(|_| DobbyError::InvalidValue(self, to)),
 
             (TypedValue::Int(i), DataType::Float) => Ok(TypedValue::Float(*i as f64)),
+            (TypedValue::Int(0), DataType::Bool) => Ok(TypedValue::Bool(false)),
+            (TypedValue::Int(1), DataType::Bool) => Ok(TypedValue::Bool(true)),
+            (TypedValue::Bool(b), DataType::Int) => Ok(TypedValue::Int(*b as i64)),
+            (TypedValue::Date(d), DataType::Timestamp) => Ok(TypedValue::Timestamp(
+                d.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+            )),
+            (TypedValue::CharInvl(c1, c2), DataType::StringInvl) => {
+                Ok(TypedValue::StringInvl(c1.to_string(), c2.to_string()))
+            }
             (TypedValue::StringInvl(s1, s2), DataType::CharInvl) => Ok(TypedValue::CharInvl(
                 string_to_char(s1)?,
                 string_to_char(s2)?,
             )),
+            // the bounds arrive as a pair of strings from JSON
+            (
+                TypedValue::StringInvl(s1, s2),
+                DataType::IntInvl | DataType::FloatInvl | DataType::TimestampInvl,
+            ) => TypedValue::String(format!("{}..{}", s1, s2))
+                .coerce(to)
+                .map_err(|_| DobbyError::InvalidValue(self, to)),
+            (TypedValue::IntInvl(i1, i2), DataType::FloatInvl) => {
+                Ok(TypedValue::FloatInvl(*i1 as f64, *i2 as f64))
+            }
             (v, _) => Err(DobbyError::InvalidValue(v.clone(), to)),
+        };
+        coerced.map(TypedValue::without_negative_zero)
+    }
+
+    /// Replaces -0.0 with 0.0: they are equal, but SQLite reads both back as 0.0,
+    /// and interval bounds are compared as text there
+    fn without_negative_zero(self) -> Self {
+        match self {
+            TypedValue::Float(f) => TypedValue::Float(f + 0.0),
+            TypedValue::FloatInvl(f1, f2) => TypedValue::FloatInvl(f1 + 0.0, f2 + 0.0),
+            value => value,
+        }
+    }
+
+    /// Coerces the value by a rule the coercion allows, saying which rule failed if any
+    pub fn coerce_with(self, to: DataType, coercion: Coercion) -> Result<Self, DobbyError> {
+        if self.data_type() == to {
+            return Ok(self);
+        }
+        let Some(rule) = CoercionRule::of(&self, to) else {
+            return Err(DobbyError::InvalidValue(self, to));
+        };
+        if !coercion.allows(rule) {
+            return Err(DobbyError::CoercionNotAllowed(self, to, rule, coercion));
         }
+        let coerced = match (&self, rule) {
+            (_, CoercionRule::Format) => Ok(TypedValue::String(self.to_string())),
+            // only floats that are ints already
+            (TypedValue::Float(f), CoercionRule::Narrowing) => {
+                (f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64)
+                    .then_some(TypedValue::Int(*f as i64))
+                    .ok_or(DobbyError::InvalidValue(self.clone(), to))
+            }
+            _ => self.clone().coerce(to),
+        };
+        coerced.map_err(|_| DobbyError::CoercionFailed(self, to, rule))
+    }
+}
+
+/// Converts a float to the decimal with its shortest text form, so that 0.1 stays 0.1
+fn decimal_from_f64(f: f64) -> Option<Decimal> {
+    Decimal::from_str_exact(&f.to_string()).ok()
+}
+
+/// Splits a condition on a part of a JSON column, written as `column.path.to.field`,
+/// into the column and the path
+pub fn split_json_path(key: &str) -> Option<(&str, &str)> {
+    key.split_once('.')
+}
+
+/// Segments of a JSON path, which are object keys or array indices
+pub fn json_path_segments(path: &str) -> Option<Vec<&str>> {
+    path.split('.')
+        .map(|segment| {
+            (!segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_'))
+                .then_some(segment)
+        })
+        .collect()
+}
+
+/// Looks up the part of the document at a path checked by `json_path_segments`
+pub fn json_lookup<'a>(
+    document: &'a serde_json::Value,
+    path: &str,
+) -> Option<&'a serde_json::Value> {
+    document.pointer(&format!("/{}", path.replace('.', "/")))
+}
+
+/// Reads text compared to a part of a JSON document as JSON when it is valid JSON.
+/// Queries written as text, like query strings, have no other way to compare numbers
+pub fn json_path_operand(key: &str, value: TypedValue) -> TypedValue {
+    match value {
+        TypedValue::String(_) | TypedValue::Char(_) if split_json_path(key).is_some() => {
+            serde_json::from_str(&value.to_string())
+                .map(TypedValue::Json)
+                .unwrap_or(value)
+        }
+        value => value,
+    }
+}
+
+/// Reads a length-prefixed byte string
+fn read_bytes<R: io::Read>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut length = [0; 8];
+    reader.read_exact(&mut length)?;
+    let mut buf = vec![0; u64::from_le_bytes(length) as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Skips a length-prefixed byte string without reading it into memory
+pub fn skip_bytes<R: io::Seek + io::Read>(reader: &mut R) -> Result<(), io::Error> {
+    let mut length = [0; 8];
+    reader.read_exact(&mut length)?;
+    reader.seek(io::SeekFrom::Current(u64::from_le_bytes(length) as i64))?;
+    Ok(())
+}
+
+fn read_timestamp<R: io::Read>(reader: &mut R) -> Result<DateTime<Utc>, io::Error> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    DateTime::from_timestamp_micros(i64::from_le_bytes(buf))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid timestamp"))
+}
+
+/// Parses an ISO 8601 timestamp, in UTC unless it has an offset,
+/// cutting it to microseconds
+fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    let timestamp = DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").map(|t| t.and_utc()))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").map(|t| t.and_utc()))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        })
+        .ok()?;
+    DateTime::from_timestamp_micros(timestamp.timestamp_micros())
+}
+
+/// (De)serializes bytes as base64 strings
+mod base64_bytes {
+    use super::BASE64;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        BASE64.decode(s).map_err(serde::de::Error::custom)
     }
 }
 
@@ -294,21 +1107,49 @@ impl From<String> for TypedValue {
     }
 }
 
+impl From<bool> for TypedValue {
+    fn from(value: bool) -> Self {
+        TypedValue::Bool(value)
+    }
+}
+
 impl From<&str> for TypedValue {
     fn from(value: &str) -> Self {
         TypedValue::String(value.to_string())
     }
 }
 
-impl ToString for TypedValue {
-    fn to_string(&self) -> String {
+impl fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            TypedValue::Int(i) => i.to_string(),
-            TypedValue::Float(f) => f.to_string(),
-            TypedValue::Char(c) => c.to_string(),
-            TypedValue::String(s) => s.to_string(),
-            TypedValue::CharInvl(c1, c2) => format!("{}..{}", c1, c2),
-            TypedValue::StringInvl(s1, s2) => format!("{}..{}", s1, s2),
+            TypedValue::Int(i) => write!(f, "{}", i),
+            TypedValue::Float(x) => write!(f, "{}", x),
+            TypedValue::Char(c) => write!(f, "{}", c),
+            TypedValue::String(s) => write!(f, "{}", s),
+            TypedValue::CharInvl(c1, c2) => write!(f, "{}..{}", c1, c2),
+            TypedValue::StringInvl(s1, s2) => write!(f, "{}..{}", s1, s2),
+            TypedValue::Bool(b) => write!(f, "{}", b),
+            TypedValue::Date(d) => write!(f, "{}", d),
+            TypedValue::Timestamp(t) => {
+                write!(
+                    f,
+                    "{}",
+                    t.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+                )
+            }
+            TypedValue::IntInvl(i1, i2) => write!(f, "{}..{}", i1, i2),
+            TypedValue::FloatInvl(f1, f2) => write!(f, "{}..{}", f1, f2),
+            TypedValue::TimestampInvl(t1, t2) => write!(
+                f,
+                "{}..{}",
+                TypedValue::Timestamp(*t1),
+                TypedValue::Timestamp(*t2)
+            ),
+            TypedValue::Bytes(b) => write!(f, "{}", BASE64.encode(b)),
+            TypedValue::Json(j) => write!(f, "{}", j),
+            TypedValue::Enum(label) => write!(f, "{}", label),
+            TypedValue::Decimal(d) => write!(f, "{}", d),
+            TypedValue::Uuid(u) => write!(f, "{}", u),
         }
     }
 }
@@ -322,6 +1163,17 @@ impl fmt::Debug for DataType {
             DataType::String => write!(f, "string"),
             DataType::CharInvl => write!(f, "char_invl"),
             DataType::StringInvl => write!(f, "string_invl"),
+            DataType::Bool => write!(f, "bool"),
+            DataType::Date => write!(f, "date"),
+            DataType::Timestamp => write!(f, "timestamp"),
+            DataType::IntInvl => write!(f, "int_invl"),
+            DataType::FloatInvl => write!(f, "float_invl"),
+            DataType::TimestampInvl => write!(f, "timestamp_invl"),
+            DataType::Bytes => write!(f, "bytes"),
+            DataType::Json => write!(f, "json"),
+            DataType::Enum => write!(f, "enum"),
+            DataType::Decimal => write!(f, "decimal"),
+            DataType::Uuid => write!(f, "uuid"),
         }
     }
 }
@@ -337,6 +1189,17 @@ impl TryFrom<&str> for DataType {
             "string" => Ok(DataType::String),
             "char_invl" => Ok(DataType::CharInvl),
             "string_invl" => Ok(DataType::StringInvl),
+            "bool" => Ok(DataType::Bool),
+            "date" => Ok(DataType::Date),
+            "timestamp" => Ok(DataType::Timestamp),
+            "int_invl" => Ok(DataType::IntInvl),
+            "float_invl" => Ok(DataType::FloatInvl),
+            "timestamp_invl" => Ok(DataType::TimestampInvl),
+            "bytes" => Ok(DataType::Bytes),
+            "json" => Ok(DataType::Json),
+            "enum" => Ok(DataType::Enum),
+            "decimal" => Ok(DataType::Decimal),
+            "uuid" => Ok(DataType::Uuid),
             _ => Err(DobbyError::InvalidDataType(s.to_string())),
         }
     }
@@ -351,6 +1214,17 @@ impl From<i32> for DataType {
             3 => DataType::String,
             4 => DataType::CharInvl,
             5 => DataType::StringInvl,
+            6 => DataType::Bool,
+            7 => DataType::Date,
+            8 => DataType::Timestamp,
+            9 => DataType::IntInvl,
+            10 => DataType::FloatInvl,
+            11 => DataType::TimestampInvl,
+            12 => DataType::Bytes,
+            13 => DataType::Json,
+            14 => DataType::Enum,
+            15 => DataType::Decimal,
+            16 => DataType::Uuid,
             _ => unreachable!("Invalid data type"),
         }
     }
@@ -359,8 +1233,13 @@ impl From<i32> for DataType {
 impl DataType {
     pub fn to_sql(&self) -> String {
         match self {
-            DataType::Int => "INTEGER".to_string(),
+            // bools are stored as 0 and 1
+            DataType::Int | DataType::Bool => "INTEGER".to_string(),
             DataType::Float => "REAL".to_string(),
+            DataType::Bytes => "BLOB".to_string(),
+            // dates and timestamps as ISO 8601 text, which sorts chronologically,
+            // intervals as `a..b` text, decimals as text to keep them exact,
+            // and uuids as hyphenated text, which sorts like their bytes
             _ => "TEXT".to_string(),
         }
     }
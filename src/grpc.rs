@@ -1,12 +1,21 @@
 use proto::database_server::{self as service, DatabaseServer};
-use proto::{query, typed_value};
+use proto::{query, rule, typed_value};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tonic::{transport::Server, Request, Response, Status};
 
-use crate::core::types::{ColumnSet, DobbyError, Query, TypedValue};
-use crate::core::Database;
+use crate::core::types::{
+    Coercion, ColumnSet, DataType, DobbyError, OnDelete, Precision, Query, Reference, Rule,
+    TypedValue, UuidVersion,
+};
+use crate::core::{
+    database::{stream_rows, Coerced},
+    schema::Schema,
+    Database,
+};
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
 
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -14,8 +23,23 @@ pub mod proto {
     tonic::include_proto!("database");
 }
 
+/// Metadata key of the coercion a request asks for instead of the server's
+pub const COERCION_KEY: &str = "x-coercion";
+
 pub struct DatabaseService {
     db: Arc<dyn Database>,
+    coercion: Coercion,
+}
+
+impl DatabaseService {
+    /// The database behind the coercion the request asks for, or the server's
+    fn coerced<T>(&self, request: &Request<T>) -> Result<Arc<dyn Database>, DobbyError> {
+        let coercion = match request.metadata().get(COERCION_KEY) {
+            Some(coercion) => coercion.to_str().unwrap_or_default().parse::<Coercion>()?,
+            None => self.coercion,
+        };
+        Ok(Arc::new(Coerced(Arc::clone(&self.db), coercion, false)))
+    }
 }
 
 #[tonic::async_trait]
@@ -24,8 +48,8 @@ impl service::Database for DatabaseService {
         &self,
         request: Request<proto::Query>,
     ) -> Result<Response<proto::Reply>, Status> {
+        let db = self.coerced(&request)?;
         let query = request.into_inner();
-        let db = Arc::clone(&self.db);
         if let Some(query) = query.query {
             let query = query.into();
             log::info!(target: "api::grpc", "Executing query: {:?}", &query);
@@ -37,13 +61,36 @@ impl service::Database for DatabaseService {
             Err(Status::invalid_argument("Query is empty"))
         }
     }
+
+    type SelectStream = Pin<Box<dyn Stream<Item = Result<proto::reply::Row, Status>> + Send>>;
+
+    #[allow(clippy::result_large_err)]
+    async fn select(
+        &self,
+        request: Request<proto::Select>,
+    ) -> Result<Response<Self::SelectStream>, Status> {
+        let db = self.coerced(&request)?;
+        let query = query::Query::Select(request.into_inner()).into();
+        log::info!(target: "api::grpc", "Streaming query: {:?}", &query);
+        let rows = ReceiverStream::new(stream_rows(db, query))
+            .map(|row| row.map(Into::into).map_err(Into::into));
+        Ok(Response::new(Box::pin(rows)))
+    }
+
+    async fn schema(
+        &self,
+        _request: Request<proto::SchemaRequest>,
+    ) -> Result<Response<proto::Schema>, Status> {
+        Ok(Response::new(self.db.schema().into()))
+    }
 }
 
 pub async fn serve(
     db: Arc<dyn Database>,
     address: impl Into<SocketAddr>,
+    coercion: Coercion,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let service = DatabaseService { db };
+    let service = DatabaseService { db, coercion };
     let address = address.into();
 
     log::info!(target: "api::grpc", "Starting gRPC server on {}", address);
@@ -62,6 +109,7 @@ impl From<DobbyError> for Status {
             DobbyError::TableNotFound(_) => Status::not_found(err.to_string()),
             DobbyError::ColumnNotFound(_, _) => Status::not_found(err.to_string()),
             DobbyError::TableAlreadyExists(_) => Status::already_exists(err.to_string()),
+            DobbyError::TableInTrash(_) => Status::already_exists(err.to_string()),
             DobbyError::ColumnAlreadyExists(_, _) => Status::already_exists(err.to_string()),
             DobbyError::NoColumns => Status::invalid_argument(err.to_string()),
             DobbyError::InvalidName(_) => Status::invalid_argument(err.to_string()),
@@ -69,22 +117,46 @@ impl From<DobbyError> for Status {
             DobbyError::InvalidDataType(_) => Status::invalid_argument(err.to_string()),
             DobbyError::IncompleteData(_, _) => Status::invalid_argument(err.to_string()),
             DobbyError::InvalidRange(_, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::NonFiniteFloat(_) => Status::invalid_argument(err.to_string()),
+            DobbyError::InvalidRule(_, _, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::RuleViolated(_, _, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::UnknownRule(_) => Status::invalid_argument(err.to_string()),
+            DobbyError::InvalidAutoIncrement(_, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::AutoIncrementExhausted(_, _) => Status::out_of_range(err.to_string()),
+            DobbyError::DuplicateKey(_, _, _) => Status::already_exists(err.to_string()),
+            DobbyError::TableChanged(_) => Status::aborted(err.to_string()),
+            DobbyError::InvalidReference(_, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::MissingReference(_, _, _) => Status::failed_precondition(err.to_string()),
+            DobbyError::Referenced(_, _) => Status::failed_precondition(err.to_string()),
+            DobbyError::InvalidLabels(_) => Status::invalid_argument(err.to_string()),
+            DobbyError::UnknownLabel(_, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::InvalidPrecision(_) => Status::invalid_argument(err.to_string()),
+            DobbyError::InvalidGenerated(_, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::PrecisionExceeded(_, _, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::CoercionNotAllowed(_, _, _, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::CoercionFailed(_, _, _) => Status::invalid_argument(err.to_string()),
+            DobbyError::UnknownCoercion(_) => Status::invalid_argument(err.to_string()),
+            DobbyError::ConversionFailed(_) => Status::invalid_argument(err.to_string()),
+            DobbyError::UnfilteredQuery(_) => Status::failed_precondition(err.to_string()),
+            DobbyError::Unsupported(_) => Status::unimplemented(err.to_string()),
+            #[cfg(feature = "sqlite")]
             DobbyError::SqlError(_) => Status::invalid_argument(err.to_string()),
             DobbyError::IoError(_) => Status::internal(err.to_string()),
         }
     }
 }
 
+impl From<ColumnSet> for proto::reply::Row {
+    fn from(row: ColumnSet) -> Self {
+        proto::reply::Row {
+            data: row.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        }
+    }
+}
+
 impl From<Vec<ColumnSet>> for proto::Reply {
     fn from(rows: Vec<ColumnSet>) -> Self {
-        proto::Reply {
-            rows: rows
-                .into_iter()
-                .map(|row| proto::reply::Row {
-                    data: row.into_iter().map(|(k, v)| (k, v.into())).collect(),
-                })
-                .collect(),
-        }
+        proto::Reply { rows: rows.into_iter().map(Into::into).collect() }
     }
 }
 
@@ -111,6 +183,33 @@ impl From<proto::query::Query> for Query {
                 .filter_map(|(k, v)| v.data.map(|v| (k, v.into())))
                 .collect()
         };
+        let labels = |labels: HashMap<String, proto::Labels>| {
+            labels
+                .into_iter()
+                .map(|(column, labels)| (column, labels.labels))
+                .collect()
+        };
+        let precisions = |precisions: HashMap<String, proto::Precision>| {
+            precisions
+                .into_iter()
+                .map(|(column, precision)| {
+                    let proto::Precision { precision, scale } = precision;
+                    (column, Precision { precision, scale })
+                })
+                .collect()
+        };
+        let generated = |generated: HashMap<String, i32>| {
+            generated
+                .into_iter()
+                .map(|(column, version)| {
+                    let version = match proto::UuidVersion::from_i32(version) {
+                        Some(proto::UuidVersion::V7) => UuidVersion::V7,
+                        _ => UuidVersion::V4,
+                    };
+                    (column, version)
+                })
+                .collect()
+        };
 
         match query {
             query::Query::Select(select) => Query::Select {
@@ -125,13 +224,44 @@ impl From<proto::query::Query> for Query {
                 table: update.table,
                 set: convert(update.set),
                 conditions: convert(update.conditions),
+                all: update.all,
             },
             query::Query::Delete(delete) => Query::Delete {
                 from: delete.from,
                 conditions: convert(delete.conditions),
+                all: delete.all,
+            },
+            query::Query::ListDeleted(list) => Query::ListDeleted {
+                from: list.from,
+                conditions: convert(list.conditions),
+            },
+            query::Query::Undelete(undelete) => Query::Undelete {
+                from: undelete.from,
+                conditions: convert(undelete.conditions),
+                all: undelete.all,
             },
             query::Query::Drop(drop) => Query::Drop { table: drop.table },
-            query::Query::Alter(alter) => Query::Alter { table: alter.table, rename: alter.rename },
+            query::Query::Undrop(undrop) => Query::Undrop { table: undrop.table },
+            query::Query::ListTrash(_) => Query::ListTrash,
+            query::Query::Purge(purge) => Query::Purge {
+                table: Some(purge.table).filter(|table| !table.is_empty()),
+            },
+            query::Query::Rename(rename) => Query::Rename { table: rename.table, to: rename.to },
+            query::Query::Alter(alter) => Query::Alter {
+                table: alter.table,
+                rename: alter.rename,
+                add: alter.add.into_iter().map(|(k, v)| (k, v.into())).collect(),
+                drop: alter.drop,
+                retype: alter
+                    .retype
+                    .into_iter()
+                    .map(|(k, v)| (k, v.into()))
+                    .collect(),
+                defaults: convert(alter.defaults),
+                labels: labels(alter.labels),
+                precisions: precisions(alter.precisions),
+                generated: generated(alter.generated),
+            },
             query::Query::Create(create) => Query::Create {
                 table: create.table,
                 columns: create
@@ -139,6 +269,24 @@ impl From<proto::query::Query> for Query {
                     .into_iter()
                     .map(|(k, v)| (k, v.into()))
                     .collect(),
+                auto_increment: Some(create.auto_increment).filter(|column| !column.is_empty()),
+                defaults: convert(create.defaults),
+                rules: create
+                    .rules
+                    .into_iter()
+                    .map(|(column, rules)| {
+                        let rules = rules.rules.into_iter().filter_map(|rule| rule.rule);
+                        (column, rules.map(Rule::from).collect())
+                    })
+                    .collect(),
+                references: create
+                    .references
+                    .into_iter()
+                    .map(|(column, reference)| (column, reference.into()))
+                    .collect(),
+                labels: labels(create.labels),
+                precisions: precisions(create.precisions),
+                generated: generated(create.generated),
             },
         }
     }
@@ -151,6 +299,38 @@ impl From<typed_value::Data> for TypedValue {
             typed_value::Data::Float(f) => TypedValue::Float(f),
             typed_value::Data::String(s) => TypedValue::String(s),
             typed_value::Data::StringInvl(i) => TypedValue::StringInvl(i.s1, i.s2),
+            typed_value::Data::Bool(b) => TypedValue::Bool(b),
+            // malformed dates are left as strings for the coercion to report
+            typed_value::Data::Date(s) => {
+                let string = TypedValue::String(s);
+                string.clone().coerce(DataType::Date).unwrap_or(string)
+            }
+            typed_value::Data::Timestamp(s) => {
+                let string = TypedValue::String(s);
+                string.clone().coerce(DataType::Timestamp).unwrap_or(string)
+            }
+            typed_value::Data::IntInvl(i) => TypedValue::IntInvl(i.i1, i.i2),
+            typed_value::Data::FloatInvl(i) => TypedValue::FloatInvl(i.f1, i.f2),
+            typed_value::Data::TimestampInvl(i) => {
+                let strings = TypedValue::StringInvl(i.t1, i.t2);
+                strings
+                    .clone()
+                    .coerce(DataType::TimestampInvl)
+                    .unwrap_or(strings)
+            }
+            typed_value::Data::Bytes(b) => TypedValue::Bytes(b),
+            typed_value::Data::Label(label) => TypedValue::Enum(label),
+            typed_value::Data::Json(s) => serde_json::from_str(&s)
+                .map(TypedValue::Json)
+                .unwrap_or(TypedValue::String(s)),
+            typed_value::Data::Decimal(s) => {
+                let string = TypedValue::String(s);
+                string.clone().coerce(DataType::Decimal).unwrap_or(string)
+            }
+            typed_value::Data::Uuid(s) => {
+                let string = TypedValue::String(s);
+                string.clone().coerce(DataType::Uuid).unwrap_or(string)
+            }
         }
     }
 }
@@ -173,6 +353,114 @@ impl From<TypedValue> for proto::TypedValue {
             TypedValue::StringInvl(s1, s2) => proto::TypedValue {
                 data: Some(typed_value::Data::StringInvl(proto::StringInvl { s1, s2 })),
             },
+            TypedValue::Bool(b) => proto::TypedValue { data: Some(typed_value::Data::Bool(b)) },
+            TypedValue::Date(d) => {
+                proto::TypedValue { data: Some(typed_value::Data::Date(d.to_string())) }
+            }
+            TypedValue::Timestamp(_) => proto::TypedValue {
+                data: Some(typed_value::Data::Timestamp(value.to_string())),
+            },
+            TypedValue::IntInvl(i1, i2) => proto::TypedValue {
+                data: Some(typed_value::Data::IntInvl(proto::IntInvl { i1, i2 })),
+            },
+            TypedValue::FloatInvl(f1, f2) => proto::TypedValue {
+                data: Some(typed_value::Data::FloatInvl(proto::FloatInvl { f1, f2 })),
+            },
+            TypedValue::TimestampInvl(t1, t2) => proto::TypedValue {
+                data: Some(typed_value::Data::TimestampInvl(proto::TimestampInvl {
+                    t1: TypedValue::Timestamp(t1).to_string(),
+                    t2: TypedValue::Timestamp(t2).to_string(),
+                })),
+            },
+            TypedValue::Bytes(b) => proto::TypedValue { data: Some(typed_value::Data::Bytes(b)) },
+            TypedValue::Json(j) => {
+                proto::TypedValue { data: Some(typed_value::Data::Json(j.to_string())) }
+            }
+            TypedValue::Enum(label) => {
+                proto::TypedValue { data: Some(typed_value::Data::Label(label)) }
+            }
+            TypedValue::Decimal(d) => proto::TypedValue {
+                data: Some(typed_value::Data::Decimal(d.to_string())),
+            },
+            TypedValue::Uuid(u) => {
+                proto::TypedValue { data: Some(typed_value::Data::Uuid(u.to_string())) }
+            }
+        }
+    }
+}
+
+impl From<Schema> for proto::Schema {
+    fn from(schema: Schema) -> Self {
+        let tables = schema
+            .tables
+            .iter()
+            .map(|(table, columns)| {
+                let columns = columns
+                    .iter()
+                    .map(|(column, data_type)| proto::schema::Column {
+                        name: column.clone(),
+                        r#type: *data_type as i32,
+                        labels: schema.column_attributes(table, column).labels,
+                    })
+                    .collect();
+                (table.clone(), proto::schema::Table { columns })
+            })
+            .collect();
+        proto::Schema { tables }
+    }
+}
+
+impl From<rule::Rule> for Rule {
+    fn from(rule: rule::Rule) -> Self {
+        match rule {
+            rule::Rule::Min(min) => Rule::Min(min),
+            rule::Rule::Max(max) => Rule::Max(max),
+            rule::Rule::MaxLength(length) => Rule::MaxLength(length as usize),
+            rule::Rule::Pattern(pattern) => Rule::Pattern(pattern),
+            rule::Rule::MinWidth(min) => Rule::MinWidth(min),
+            rule::Rule::MaxWidth(max) => Rule::MaxWidth(max),
+        }
+    }
+}
+
+impl From<Rule> for proto::Rule {
+    fn from(rule: Rule) -> Self {
+        let rule = match rule {
+            Rule::Min(min) => rule::Rule::Min(min),
+            Rule::Max(max) => rule::Rule::Max(max),
+            Rule::MaxLength(length) => rule::Rule::MaxLength(length as u64),
+            Rule::Pattern(pattern) => rule::Rule::Pattern(pattern),
+            Rule::MinWidth(min) => rule::Rule::MinWidth(min),
+            Rule::MaxWidth(max) => rule::Rule::MaxWidth(max),
+        };
+        proto::Rule { rule: Some(rule) }
+    }
+}
+
+impl From<proto::Reference> for Reference {
+    fn from(reference: proto::Reference) -> Self {
+        let on_delete = match reference.on_delete() {
+            proto::reference::OnDelete::Restrict => OnDelete::Restrict,
+            proto::reference::OnDelete::Cascade => OnDelete::Cascade,
+        };
+        Reference {
+            table: reference.table,
+            column: reference.column,
+            on_delete,
+        }
+    }
+}
+
+impl From<Reference> for proto::Reference {
+    fn from(reference: Reference) -> Self {
+        let on_delete = match reference.on_delete {
+            OnDelete::Restrict => proto::reference::OnDelete::Restrict,
+            OnDelete::Cascade => proto::reference::OnDelete::Cascade,
+        };
+        proto::Reference {
+            table: reference.table,
+            column: reference.column,
+            on_delete: on_delete as i32,
         }
     }
 }
@@ -1,5 +1,11 @@
-use crate::core::types::{ColumnSet, DataType, DobbyError, Query};
-use crate::core::Database;
+use crate::core::types::{
+    json_path_operand, Coercion, ColumnSet, DataType, DobbyError, Precision, Query, Reference,
+    Rule, TypedValue, UuidVersion,
+};
+use crate::core::{
+    database::{stream_rows, Coerced},
+    Database,
+};
 
 use std::collections::HashMap;
 use std::convert::Infallible;
@@ -7,9 +13,121 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 
 use once_cell::sync::Lazy;
-use warp::http::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::{body::Bytes, Body};
 use warp::Filter;
 
+#[derive(Debug, Deserialize)]
+struct Renaming {
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Creation {
+    auto_increment: Option<String>,
+}
+
+/// Column of the `create` body: either just its datatype,
+/// or the datatype with a default value, rules, a reference, enum labels
+/// the precision and scale of a decimal or the version of generated uuids
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ColumnDefinition {
+    Type(DataType),
+    Detailed {
+        #[serde(rename = "type")]
+        data_type: DataType,
+        default: Option<TypedValue>,
+        #[serde(default)]
+        rules: Vec<Rule>,
+        references: Option<Reference>,
+        #[serde(default)]
+        labels: Vec<String>,
+        precision: Option<u32>,
+        #[serde(default)]
+        scale: u32,
+        generate: Option<UuidVersion>,
+    },
+}
+
+fn create_query(
+    table: String,
+    auto_increment: Option<String>,
+    definitions: HashMap<String, ColumnDefinition>,
+) -> Query {
+    let mut columns = Vec::new();
+    let mut defaults = HashMap::new();
+    let mut rules = HashMap::new();
+    let mut references = HashMap::new();
+    let mut labels = HashMap::new();
+    let mut precisions = HashMap::new();
+    let mut generated = HashMap::new();
+    for (column, definition) in definitions {
+        match definition {
+            ColumnDefinition::Type(data_type) => columns.push((column, data_type)),
+            ColumnDefinition::Detailed {
+                data_type,
+                default,
+                rules: column_rules,
+                references: reference,
+                labels: column_labels,
+                precision,
+                scale,
+                generate,
+            } => {
+                columns.push((column.clone(), data_type));
+                if let Some(default) = default {
+                    defaults.insert(column.clone(), default);
+                }
+                if let Some(reference) = reference {
+                    references.insert(column.clone(), reference);
+                }
+                if !column_labels.is_empty() {
+                    labels.insert(column.clone(), column_labels);
+                }
+                if let Some(precision) = precision {
+                    precisions.insert(column.clone(), Precision { precision, scale });
+                }
+                if let Some(version) = generate {
+                    generated.insert(column.clone(), version);
+                }
+                rules.insert(column, column_rules);
+            }
+        }
+    }
+    Query::Create {
+        table,
+        columns,
+        auto_increment,
+        defaults,
+        rules,
+        references,
+        labels,
+        precisions,
+        generated,
+    }
+}
+
+/// Body of the `alter` request, in addition to column renamings in the query
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Alteration {
+    add: HashMap<String, DataType>,
+    drop: Vec<String>,
+    retype: HashMap<String, DataType>,
+    defaults: ColumnSet,
+    labels: HashMap<String, Vec<String>>,
+    precisions: HashMap<String, Precision>,
+    generated: HashMap<String, UuidVersion>,
+}
+
+const NDJSON: &str = "application/x-ndjson";
+
+/// Header of the coercion a request asks for instead of the server's
+const COERCION_HEADER: &str = "x-coercion";
+
 impl warp::reject::Reject for DobbyError {}
 
 static OPENAPI_SPEC: Lazy<serde_json::Value> = Lazy::new(|| {
@@ -21,6 +139,7 @@ impl DobbyError {
     pub fn status_code(&self) -> StatusCode {
         match self {
             DobbyError::TableAlreadyExists(_) => StatusCode::CONFLICT,
+            DobbyError::TableInTrash(_) => StatusCode::CONFLICT,
             DobbyError::TableNotFound(_) => StatusCode::NOT_FOUND,
             DobbyError::ColumnAlreadyExists(_, _) => StatusCode::CONFLICT,
             DobbyError::ColumnNotFound(_, _) => StatusCode::NOT_FOUND,
@@ -30,56 +149,117 @@ impl DobbyError {
             DobbyError::IncompleteData(_, _) => StatusCode::BAD_REQUEST,
             DobbyError::InvalidDataType(_) => StatusCode::BAD_REQUEST,
             DobbyError::InvalidRange(_, _) => StatusCode::BAD_REQUEST,
+            DobbyError::NonFiniteFloat(_) => StatusCode::BAD_REQUEST,
+            DobbyError::InvalidRule(_, _, _) => StatusCode::BAD_REQUEST,
+            DobbyError::RuleViolated(_, _, _) => StatusCode::BAD_REQUEST,
+            DobbyError::UnknownRule(_) => StatusCode::BAD_REQUEST,
+            DobbyError::InvalidAutoIncrement(_, _) => StatusCode::BAD_REQUEST,
+            DobbyError::AutoIncrementExhausted(_, _) => StatusCode::BAD_REQUEST,
+            DobbyError::DuplicateKey(_, _, _) => StatusCode::CONFLICT,
+            DobbyError::TableChanged(_) => StatusCode::CONFLICT,
+            DobbyError::InvalidReference(_, _) => StatusCode::BAD_REQUEST,
+            DobbyError::MissingReference(_, _, _) => StatusCode::CONFLICT,
+            DobbyError::Referenced(_, _) => StatusCode::CONFLICT,
+            DobbyError::InvalidLabels(_) => StatusCode::BAD_REQUEST,
+            DobbyError::UnknownLabel(_, _) => StatusCode::BAD_REQUEST,
+            DobbyError::InvalidPrecision(_) => StatusCode::BAD_REQUEST,
+            DobbyError::InvalidGenerated(_, _) => StatusCode::BAD_REQUEST,
+            DobbyError::PrecisionExceeded(_, _, _) => StatusCode::BAD_REQUEST,
+            DobbyError::CoercionNotAllowed(_, _, _, _) => StatusCode::BAD_REQUEST,
+            DobbyError::CoercionFailed(_, _, _) => StatusCode::BAD_REQUEST,
+            DobbyError::UnknownCoercion(_) => StatusCode::BAD_REQUEST,
+            DobbyError::ConversionFailed(_) => StatusCode::BAD_REQUEST,
+            DobbyError::UnfilteredQuery(_) => StatusCode::PRECONDITION_FAILED,
+            DobbyError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            #[cfg(feature = "sqlite")]
             DobbyError::SqlError(_) => StatusCode::BAD_REQUEST,
             DobbyError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-pub async fn serve(db_itself: Arc<dyn Database>, address: impl Into<SocketAddr>) {
-    let db = Arc::clone(&db_itself);
+pub async fn serve(
+    db_itself: Arc<dyn Database>,
+    address: impl Into<SocketAddr>,
+    coercion: Coercion,
+) {
     let select = warp::get()
         .and(warp::path::param())
         .and(warp::path::end())
-        .and(warp::query::<ColumnSet>())
-        .and_then(move |from: String, conditions: ColumnSet| {
-            let db = Arc::clone(&db);
-            execute_on(db, Query::Select { from, conditions, columns: vec![] })
-        });
+        .and(coerced(&db_itself, coercion))
+        .and(conditions())
+        .and(warp::header::optional::<String>("accept"))
+        .and_then(
+            |from: String, db: Arc<dyn Database>, conditions: ColumnSet, accept: Option<String>| {
+                let ndjson = accept.is_some_and(|accept| accept.contains(NDJSON));
+                stream_on(
+                    db,
+                    Query::Select { from, conditions, columns: vec![] },
+                    ndjson,
+                )
+            },
+        );
 
-    let db = Arc::clone(&db_itself);
     let insert = warp::post()
         .and(warp::path::param())
         .and(warp::path::end())
+        .and(coerced(&db_itself, coercion))
         .and(warp::body::json())
-        .and_then(move |into: String, values: ColumnSet| {
-            let db = Arc::clone(&db);
+        .and_then(|into: String, db: Arc<dyn Database>, values: ColumnSet| {
             execute_on(db, Query::Insert { into, values })
         })
         .map(|reply| warp::reply::with_status(reply, StatusCode::CREATED));
 
-    let db = Arc::clone(&db_itself);
     let update = warp::put()
         .and(warp::path::param())
         .and(warp::path::end())
-        .and(warp::query::<ColumnSet>())
+        .and(coerced(&db_itself, coercion))
+        .and(conditions_or_all())
         .and(warp::body::json())
         .and_then(
-            move |table: String, conditions: ColumnSet, set: ColumnSet| {
-                let db = Arc::clone(&db);
-                execute_on(db, Query::Update { table, conditions, set })
+            |table: String,
+             db: Arc<dyn Database>,
+             conditions: ColumnSet,
+             all: bool,
+             set: ColumnSet| {
+                execute_on(db, Query::Update { table, conditions, set, all })
             },
         );
 
-    let db = Arc::clone(&db_itself);
     let delete = warp::delete()
         .and(warp::path::param())
         .and(warp::path::end())
-        .and(warp::query::<ColumnSet>())
-        .and_then(move |from: String, conditions: ColumnSet| {
-            let db = Arc::clone(&db);
-            execute_on(db, Query::Delete { from, conditions })
-        });
+        .and(coerced(&db_itself, coercion))
+        .and(conditions_or_all())
+        .and_then(
+            |from: String, db: Arc<dyn Database>, conditions: ColumnSet, all: bool| {
+                execute_on(db, Query::Delete { from, conditions, all })
+            },
+        );
+
+    let list_deleted = warp::get()
+        .and(warp::path::param())
+        .and(warp::path("deleted"))
+        .and(warp::path::end())
+        .and(coerced(&db_itself, coercion))
+        .and(conditions())
+        .and_then(
+            |from: String, db: Arc<dyn Database>, conditions: ColumnSet| {
+                execute_on(db, Query::ListDeleted { from, conditions })
+            },
+        );
+
+    let undelete = warp::put()
+        .and(warp::path::param())
+        .and(warp::path("undelete"))
+        .and(warp::path::end())
+        .and(coerced(&db_itself, coercion))
+        .and(conditions_or_all())
+        .and_then(
+            |from: String, db: Arc<dyn Database>, conditions: ColumnSet, all: bool| {
+                execute_on(db, Query::Undelete { from, conditions, all })
+            },
+        );
 
     let db = Arc::clone(&db_itself);
     let drop = warp::delete()
@@ -92,28 +272,105 @@ pub async fn serve(db_itself: Arc<dyn Database>, address: impl Into<SocketAddr>)
         });
 
     let db = Arc::clone(&db_itself);
+    let undrop = warp::put()
+        .and(warp::path::param())
+        .and(warp::path("undrop"))
+        .and(warp::path::end())
+        .and_then(move |table: String| {
+            let db = Arc::clone(&db);
+            execute_on(db, Query::Undrop { table })
+        });
+
+    let db = Arc::clone(&db_itself);
+    let list_trash = warp::get()
+        .and(warp::path(".trash"))
+        .and(warp::path::end())
+        .and_then(move || {
+            let db = Arc::clone(&db);
+            execute_on(db, Query::ListTrash)
+        });
+
+    let db = Arc::clone(&db_itself);
+    let purge = warp::delete()
+        .and(warp::path(".trash"))
+        .and(
+            warp::path::param()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and_then(move |table: Option<String>| {
+            let db = Arc::clone(&db);
+            execute_on(db, Query::Purge { table })
+        });
+
+    let db = Arc::clone(&db_itself);
+    let rename = warp::put()
+        .and(warp::path::param())
+        .and(warp::path("rename"))
+        .and(warp::path::end())
+        .and(warp::query::<Renaming>())
+        .and_then(move |table: String, Renaming { to }| {
+            let db = Arc::clone(&db);
+            execute_on(db, Query::Rename { table, to })
+        });
+
     let create = warp::post()
         .and(warp::path::param())
         .and(warp::path("create"))
         .and(warp::path::end())
+        .and(coerced(&db_itself, coercion))
+        .and(warp::query::<Creation>())
         .and(warp::body::json())
-        .and_then(move |table: String, columns: HashMap<String, DataType>| {
-            let db = Arc::clone(&db);
-            let columns = Vec::from_iter(columns.into_iter());
-            execute_on(db, Query::Create { table, columns })
-        })
+        .and_then(
+            |table: String,
+             db: Arc<dyn Database>,
+             Creation { auto_increment }: Creation,
+             definitions: HashMap<String, ColumnDefinition>| {
+                execute_on(db, create_query(table, auto_increment, definitions))
+            },
+        )
         .map(|reply| warp::reply::with_status(reply, StatusCode::CREATED));
 
-    let db = Arc::clone(&db_itself);
     let alter = warp::put()
         .and(warp::path::param())
         .and(warp::path("alter"))
         .and(warp::path::end())
+        .and(coerced(&db_itself, coercion))
         .and(warp::query::<HashMap<String, String>>())
-        .and_then(move |table: String, rename: HashMap<String, String>| {
-            let db = Arc::clone(&db);
-            execute_on(db, Query::Alter { table, rename })
-        });
+        .and(optional_json::<Alteration>())
+        .and_then(
+            |table: String,
+             db: Arc<dyn Database>,
+             rename: HashMap<String, String>,
+             alteration: Alteration| {
+                let Alteration {
+                    add,
+                    drop,
+                    retype,
+                    defaults,
+                    labels,
+                    precisions,
+                    generated,
+                } = alteration;
+                let add = Vec::from_iter(add);
+                execute_on(
+                    db,
+                    Query::Alter {
+                        table,
+                        rename,
+                        add,
+                        drop,
+                        retype,
+                        defaults,
+                        labels,
+                        precisions,
+                        generated,
+                    },
+                )
+            },
+        );
 
     let db = Arc::clone(&db_itself);
     let schema = warp::get()
@@ -134,7 +391,13 @@ pub async fn serve(db_itself: Arc<dyn Database>, address: impl Into<SocketAddr>)
         .or(insert)
         .or(update)
         .or(delete)
+        .or(list_deleted)
+        .or(undelete)
         .or(drop)
+        .or(undrop)
+        .or(list_trash)
+        .or(purge)
+        .or(rename)
         .or(create)
         .or(alter)
         .or(schema)
@@ -146,6 +409,65 @@ pub async fn serve(db_itself: Arc<dyn Database>, address: impl Into<SocketAddr>)
     warp::serve(routes).run(address).await;
 }
 
+/// The database behind the coercion the request asks for in its header, or the server's,
+/// with the conditions of the query string as text
+fn coerced(
+    db: &Arc<dyn Database>,
+    coercion: Coercion,
+) -> impl Filter<Extract = (Arc<dyn Database>,), Error = warp::Rejection> + Clone {
+    let db = Arc::clone(db);
+    warp::header::optional::<String>(COERCION_HEADER).and_then(move |header: Option<String>| {
+        let db = Arc::clone(&db);
+        async move {
+            let coercion = match header {
+                Some(header) => header.parse::<Coercion>()?,
+                None => coercion,
+            };
+            Ok::<_, warp::Rejection>(Arc::new(Coerced(db, coercion, true)) as Arc<dyn Database>)
+        }
+    })
+}
+
+/// The conditions of the query string, and whether `all=true` confirms that an update
+/// or delete is meant to affect every row. `all` is one of the `RESERVED_COLUMNS`,
+/// so it's never a condition.
+fn conditions_or_all() -> impl Filter<Extract = (ColumnSet, bool), Error = warp::Rejection> + Clone
+{
+    conditions()
+        .map(|mut conditions: ColumnSet| {
+            let all = conditions.remove("all") == Some(TypedValue::from("true"));
+            (conditions, all)
+        })
+        .untuple_one()
+}
+
+/// The conditions of the query string, where `payload.age=42` compares a number
+fn conditions() -> impl Filter<Extract = (ColumnSet,), Error = warp::Rejection> + Clone {
+    warp::query::<ColumnSet>().map(|conditions: ColumnSet| {
+        conditions
+            .into_iter()
+            .map(|(key, value)| {
+                let value = json_path_operand(&key, value);
+                (key, value)
+            })
+            .collect()
+    })
+}
+
+/// A JSON body that falls back to the default value when the body is empty
+fn optional_json<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: DeserializeOwned + Default + Send,
+{
+    warp::body::bytes().and_then(|body: Bytes| async move {
+        if body.is_empty() {
+            Ok(T::default())
+        } else {
+            serde_json::from_slice(&body).map_err(|_| warp::reject())
+        }
+    })
+}
+
 async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
     if let Some(error) = err.find::<DobbyError>() {
         Ok(warp::reply::with_status(
@@ -167,3 +489,45 @@ async fn execute_on(
     let result = db.execute(query)?;
     Ok(warp::reply::json(&result))
 }
+
+/// Streams the resulting rows either as a chunked JSON array or as
+/// newline-delimited JSON, without collecting them in memory
+async fn stream_on(
+    db: Arc<dyn Database>,
+    query: Query,
+    ndjson: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut receiver = stream_rows(db, query);
+
+    // errors that happen before the first row get a proper status code
+    let first = match receiver.recv().await {
+        Some(Err(err)) => return Err(err.into()),
+        first => first,
+    };
+
+    let mut first_row = true;
+    let rows = tokio_stream::iter(first)
+        .chain(ReceiverStream::new(receiver))
+        .map(move |row| {
+            let row = serde_json::to_string(&row?).unwrap();
+            let chunk = match (ndjson, first_row) {
+                (true, _) => row + "\n",
+                (false, true) => row,
+                (false, false) => format!(",{}", row),
+            };
+            first_row = false;
+            Ok::<_, DobbyError>(chunk)
+        });
+
+    let (open, close) = if ndjson { ("", "") } else { ("[", "]") };
+    let body = tokio_stream::once(Ok(open.to_string()))
+        .chain(rows)
+        .chain(tokio_stream::once(Ok(close.to_string())));
+
+    Ok(Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            if ndjson { NDJSON } else { "application/json" },
+        )
+        .body(Body::wrap_stream(body)))
+}